@@ -0,0 +1,259 @@
+//! Timestamped backups of the hosts file, taken before it is modified.
+
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::hosts_file::LockedFile;
+
+/// When the hosts file is backed up during a session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum BackupWhen {
+    /// Before the first write only
+    #[default]
+    FirstWrite,
+    /// Before every write
+    EveryWrite,
+}
+
+/// Where backups of one hosts file go and how many are kept.
+#[derive(Debug, Clone)]
+pub struct Backups {
+    hosts_file: PathBuf,
+    dir: PathBuf,
+    keep: usize,
+}
+
+impl Backups {
+    /// Backups are named `<hosts file name>.<timestamp>.bak` and stored in
+    /// `dir`, or next to the hosts file when it isn't given.
+    pub fn new(hosts_file: &Path, backup_dir: Option<PathBuf>, keep: usize) -> Self {
+        let dir = backup_dir.unwrap_or_else(|| {
+            hosts_file
+                .parent()
+                .filter(|p| !p.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new("."))
+                .to_path_buf()
+        });
+        Self {
+            hosts_file: hosts_file.to_path_buf(),
+            dir,
+            keep,
+        }
+    }
+
+    fn prefix(&self) -> String {
+        let name = self
+            .hosts_file
+            .file_name()
+            .map_or_else(|| "hosts".into(), |n| n.to_string_lossy());
+        format!("{name}.")
+    }
+
+    /// Saves `contents` of the hosts file as a new backup and prunes all but
+    /// the newest `keep` backups. Returns the path of the new backup. Callers
+    /// pass what they read under the file lock, instead of copying the file.
+    pub fn save(&self, contents: &str) -> Result<PathBuf> {
+        let mut time = SystemTime::now();
+        let backup = loop {
+            // RFC 3339 without colons, which aren't allowed in Windows file
+            // names. It still sorts chronologically.
+            let timestamp = humantime::format_rfc3339_millis(time)
+                .to_string()
+                .replace(':', "");
+            let backup = self.dir.join(format!("{}{timestamp}.bak", self.prefix()));
+            let created = File::options()
+                .write(true)
+                .create_new(true)
+                .open(&backup)
+                .and_then(|mut file| file.write_all(contents.as_bytes()));
+            match created {
+                Ok(()) => break backup,
+                // Taken by a backup from the same millisecond: use the next one
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    time += Duration::from_millis(1);
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!(
+                            "Failed to back up {} to {}",
+                            self.hosts_file.display(),
+                            backup.display()
+                        )
+                    })
+                }
+            }
+        };
+
+        for old in self.list()?.iter().skip(self.keep) {
+            fs::remove_file(old)
+                .with_context(|| format!("Failed to remove old backup {}", old.display()))?;
+        }
+        Ok(backup)
+    }
+
+    /// Lists the backups of the hosts file, newest first.
+    pub fn list(&self) -> Result<Vec<PathBuf>> {
+        let prefix = self.prefix();
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", self.dir.display()))
+            }
+        };
+
+        // Only names `save` generates: the directory may be /etc, with other
+        // `hosts.*.bak` files that mustn't be restored or pruned
+        let mut backups: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|n| n.to_str())
+                    .and_then(|n| n.strip_prefix(&prefix))
+                    .and_then(|n| n.strip_suffix(".bak"))
+                    .is_some_and(is_timestamp)
+            })
+            .collect();
+        backups.sort_unstable_by(|a, b| b.cmp(a));
+        Ok(backups)
+    }
+
+    /// Replaces the contents of the hosts file with `backup`, after backing up
+    /// the current contents in case the wrong backup was picked. Returns that
+    /// new backup. The file is written in place rather than replaced, so bind
+    /// mounts keep working.
+    pub async fn restore(&self, backup: &Path, lock_timeout: Duration) -> Result<PathBuf> {
+        // Read it first, pruning may remove it
+        let contents = fs::read_to_string(backup)
+            .with_context(|| format!("Failed to read {}", backup.display()))?;
+        let mut file = LockedFile::open(&self.hosts_file, lock_timeout).await?;
+        let previous = self.save(&file.read()?)?;
+        file.write(&contents)?;
+        Ok(previous)
+    }
+}
+
+/// Whether `stamp` is a timestamp as `save` puts it in backup names, i.e.
+/// RFC 3339 without colons, e.g. `2024-05-01T120000.000Z`.
+fn is_timestamp(stamp: &str) -> bool {
+    let (Some(date), Some(hours), Some(minutes), Some(seconds)) = (
+        stamp.get(..11),
+        stamp.get(11..13),
+        stamp.get(13..15),
+        stamp.get(15..),
+    ) else {
+        return false;
+    };
+    !stamp.contains(':')
+        && humantime::parse_rfc3339_weak(&format!("{date}{hours}:{minutes}:{seconds}")).is_ok()
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::indexing_slicing
+)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_save_prunes_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let hosts_file = dir.path().join("hosts");
+        let backups = Backups::new(&hosts_file, None, 2);
+        assert!(backups.list().unwrap().is_empty());
+
+        for version in 1..=3 {
+            backups.save(&format!("version {version}\n")).unwrap();
+            // Backups are named by the millisecond
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        fs::write(dir.path().join("unrelated.bak"), "").unwrap();
+        // Files of the user's next to /etc/hosts
+        for foreign in ["hosts.bak", "hosts.before-vpn.bak", "hosts.1.bak"] {
+            fs::write(dir.path().join(foreign), "mine\n").unwrap();
+        }
+
+        let list = backups.list().unwrap();
+        assert_eq!(list.len(), 2, "only the newest two are kept");
+        assert_eq!(fs::read_to_string(&list[0]).unwrap(), "version 3\n");
+        assert_eq!(fs::read_to_string(&list[1]).unwrap(), "version 2\n");
+
+        fs::write(&hosts_file, "version 3\n").unwrap();
+        let previous = backups
+            .restore(&list[1], Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&hosts_file).unwrap(),
+            "version 2\n",
+            "the oldest kept backup is restored even though restoring prunes it"
+        );
+        assert_eq!(fs::read_to_string(previous).unwrap(), "version 3\n");
+        for foreign in ["hosts.bak", "hosts.before-vpn.bak", "hosts.1.bak"] {
+            assert!(
+                dir.path().join(foreign).exists(),
+                "{foreign} is neither listed nor pruned"
+            );
+        }
+    }
+
+    #[test]
+    fn test_backups_in_the_same_millisecond_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let backups = Backups::new(&dir.path().join("hosts"), None, 10);
+        for version in 1..=5 {
+            backups.save(&format!("version {version}\n")).unwrap();
+        }
+
+        let contents: Vec<String> = backups
+            .list()
+            .unwrap()
+            .iter()
+            .map(|backup| fs::read_to_string(backup).unwrap())
+            .collect();
+        assert_eq!(
+            contents,
+            (1..=5)
+                .rev()
+                .map(|version| format!("version {version}\n"))
+                .collect::<Vec<_>>(),
+            "none is overwritten, and they still sort newest first"
+        );
+    }
+
+    #[test]
+    fn test_is_timestamp() {
+        assert!(is_timestamp("2024-05-01T120000.000Z"));
+        assert!(!is_timestamp("before-vpn"));
+        assert!(!is_timestamp("1"));
+        assert!(!is_timestamp("2024-05-01T12:00:00.000Z"));
+        assert!(!is_timestamp("2024-13-01T120000.000Z"));
+    }
+
+    #[test]
+    fn test_separate_backup_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let hosts_file = dir.path().join("hosts");
+        fs::write(&hosts_file, "127.0.0.1 localhost\n").unwrap();
+
+        let backups = Backups::new(&hosts_file, Some(dir.path().join("missing")), 5);
+        assert!(
+            backups.list().unwrap().is_empty(),
+            "a missing backup directory has no backups"
+        );
+
+        let backups = Backups::new(&hosts_file, Some(dir.path().to_path_buf()), 5);
+        let backup = backups.save("127.0.0.1 localhost\n").unwrap();
+        assert!(backup
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("hosts."));
+    }
+}
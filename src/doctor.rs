@@ -0,0 +1,407 @@
+//! Environment checks behind the `doctor` command: the things that most often
+//! keep hostnames from resolving even though the tool itself works.
+
+use bollard::Docker;
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+
+use crate::hosts_file::{self, Markers};
+
+const NSSWITCH_CONF: &str = "/etc/nsswitch.conf";
+const MOUNTINFO: &str = "/proc/self/mountinfo";
+/// Files Docker and Podman create at the root of a container.
+const CONTAINER_MARKERS: [&str; 2] = ["/.dockerenv", "/run/.containerenv"];
+const RESOLVED_CONF: &str = "/etc/systemd/resolved.conf";
+const RESOLVED_STUB: &str = "/run/systemd/resolve/stub-resolv.conf";
+
+/// Outcome of a single check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// Works, but may not behave as expected.
+    Warn,
+    /// Hostnames won't resolve until this is fixed.
+    Fail,
+}
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub message: String,
+    /// What to do about it, for warnings and failures.
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Ok,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Warn,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: &'static str, message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Fail,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// Runs every check, in the order a problem would be looked for.
+/// `instance` selects the managed section, as `--instance` does.
+pub async fn run(socket: &str, hosts_file: &Path, instance: Option<&str>) -> Vec<Check> {
+    let mut checks = vec![check_docker(socket).await, check_writable(hosts_file)];
+    checks.extend(check_bind_mount(hosts_file));
+    checks.push(match fs::read_to_string(hosts_file) {
+        Ok(content) => check_markers(&content, instance),
+        Err(e) => Check::fail(
+            "markers",
+            format!("Can't read {}: {e}", hosts_file.display()),
+            "Check that the path is right and readable",
+        ),
+    });
+    if let Ok(content) = fs::read_to_string(NSSWITCH_CONF) {
+        checks.push(check_nsswitch(&content));
+    }
+    checks.extend(check_caches());
+    checks
+}
+
+async fn check_docker(socket: &str) -> Check {
+    const NAME: &str = "docker";
+    let hint = "Check that Docker is running and that --socket (DOCKER_SOCKET) points at it; \
+                without root, the user needs to be in the docker group";
+    let docker = match Docker::connect_with_socket(socket, 10, bollard::API_DEFAULT_VERSION) {
+        Ok(docker) => docker,
+        Err(e) => return Check::fail(NAME, format!("Can't connect to {socket}: {e}"), hint),
+    };
+    match docker.version().await {
+        Ok(version) => Check::ok(
+            NAME,
+            format!(
+                "Docker {} at {socket}, API {}",
+                version.version.unwrap_or_default(),
+                version.api_version.unwrap_or_default()
+            ),
+        ),
+        Err(e) => Check::fail(NAME, format!("{socket} isn't answering: {e}"), hint),
+    }
+}
+
+fn check_writable(hosts_file: &Path) -> Check {
+    const NAME: &str = "hosts file";
+    // Opening for writing without truncating leaves the file as it is
+    match OpenOptions::new().write(true).open(hosts_file) {
+        Ok(_) => Check::ok(NAME, format!("{} is writable", hosts_file.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => Check::fail(
+            NAME,
+            format!("{} isn't writable", hosts_file.display()),
+            "Run sync as root (e.g. with sudo), or mount the file read-write into the container",
+        ),
+        Err(e) if e.kind() == std::io::ErrorKind::ReadOnlyFilesystem => Check::fail(
+            NAME,
+            format!("{} is on a read-only file system", hosts_file.display()),
+            "Mount the file read-write into the container, i.e. without :ro, and without a read-only root file system",
+        ),
+        Err(e) => Check::fail(
+            NAME,
+            format!("Can't open {}: {e}", hosts_file.display()),
+            "Check that the path is right",
+        ),
+    }
+}
+
+/// Reports whether `hosts_file` is a bind mount, which is how it gets into a
+/// container. Only known on Linux.
+fn check_bind_mount(hosts_file: &Path) -> Option<Check> {
+    let mountinfo = fs::read_to_string(MOUNTINFO).ok()?;
+    let path = fs::canonicalize(hosts_file).unwrap_or_else(|_| hosts_file.to_path_buf());
+    let in_container = CONTAINER_MARKERS.iter().any(|m| Path::new(m).exists());
+    Some(bind_mount_check(
+        &path,
+        mount_source(&mountinfo, &path.to_string_lossy()),
+        in_container,
+    ))
+}
+
+fn bind_mount_check(path: &Path, mounted_from: Option<String>, in_container: bool) -> Check {
+    const NAME: &str = "bind mount";
+    match mounted_from {
+        Some(source) => Check::ok(
+            NAME,
+            format!(
+                "{} is a bind mount of {source}; it's written in place, so the mount keeps working. \
+                 Make sure it is the host's hosts file, not the one Docker generates for the container",
+                path.display()
+            ),
+        ),
+        // Then only the container's own copy is written, which the host never reads
+        None if in_container => Check::warn(
+            NAME,
+            format!(
+                "{} isn't a bind mount, but this is running in a container",
+                path.display()
+            ),
+            "Mount the host's hosts file into the container, e.g. with -v /etc/hosts:/etc/hosts",
+        ),
+        None => Check::ok(NAME, format!("{} isn't a bind mount", path.display())),
+    }
+}
+
+/// Finds the mount of a single file at `path` in `/proc/self/mountinfo`
+/// format and returns its source path within the mounted filesystem.
+fn mount_source(mountinfo: &str, path: &str) -> Option<String> {
+    mountinfo.lines().rev().find_map(|line| {
+        let mut fields = line.split(' ').skip(3);
+        let root = fields.next()?;
+        let mount_point = fields.next()?;
+        // Spaces and other special characters are octal-escaped
+        (mount_point.replace("\\040", " ") == path && root != "/")
+            .then(|| root.replace("\\040", " "))
+    })
+}
+
+fn check_markers(content: &str, instance: Option<&str>) -> Check {
+    const NAME: &str = "markers";
+    let markers = instance.map_or_else(Markers::default, Markers::for_instance);
+    let mut own = hosts_file::sections(content)
+        .into_iter()
+        .filter(|section| section.instance.as_deref() == instance);
+    let hint =
+        "It is repaired on the next write; run `docker-hostmanager clean` to remove it instead";
+    match (own.next(), own.next()) {
+        (None, _) => Check::ok(
+            NAME,
+            format!(
+                "No {} section yet, it will be added on the first sync",
+                markers.start
+            ),
+        ),
+        (Some(section), None) => match (section.start_line, section.end_line) {
+            (Some(start), Some(end)) => Check::ok(
+                NAME,
+                format!("One well-formed section on lines {start}-{end}"),
+            ),
+            (Some(start), None) => Check::warn(
+                NAME,
+                format!("{} on line {start} has no end marker", markers.start),
+                hint,
+            ),
+            (None, _) => Check::warn(
+                NAME,
+                format!("{} without a start marker", markers.end),
+                hint,
+            ),
+        },
+        (Some(_), Some(_)) => Check::warn(NAME, "More than one managed section", hint),
+    }
+}
+
+/// Checks that the `hosts:` line of `nsswitch.conf` consults `files`, i.e.
+/// the hosts file, and that nothing before it can answer first.
+fn check_nsswitch(content: &str) -> Check {
+    const NAME: &str = "nsswitch";
+    let Some(line) = content.lines().find_map(|line| {
+        line.split('#')
+            .next()
+            .and_then(|l| l.trim().strip_prefix("hosts:"))
+    }) else {
+        return Check::fail(
+            NAME,
+            format!("{NSSWITCH_CONF} has no hosts: line"),
+            "Add `hosts: files dns`",
+        );
+    };
+    let sources: Vec<&str> = line.split_whitespace().collect();
+    match sources.iter().position(|s| *s == "files") {
+        None => Check::fail(
+            NAME,
+            format!("The hosts: line of {NSSWITCH_CONF} doesn't list files"),
+            "Add `files` at the start of the hosts: line, otherwise the hosts file is never read",
+        ),
+        Some(0) => Check::ok(NAME, "The hosts file is consulted first"),
+        Some(i) => Check::warn(
+            NAME,
+            format!(
+                "The hosts file is consulted after {}",
+                sources.get(..i).unwrap_or_default().join(" ")
+            ),
+            "Those sources may answer first (mdns for .local names, for example); \
+             move `files` to the start of the hosts: line",
+        ),
+    }
+}
+
+/// Looks for caching resolvers that may keep serving old entries.
+fn check_caches() -> Vec<Check> {
+    const NAME: &str = "caching";
+    let mut checks = Vec::new();
+    if Path::new(RESOLVED_STUB).exists() {
+        let reads_hosts =
+            fs::read_to_string(RESOLVED_CONF).map_or(true, |conf| resolved_reads_etc_hosts(&conf));
+        checks.push(if reads_hosts {
+            Check::ok(
+                NAME,
+                "systemd-resolved is running and picks up hosts file changes",
+            )
+        } else {
+            Check::warn(
+                NAME,
+                format!(
+                    "systemd-resolved is running with ReadEtcHosts turned off in {RESOLVED_CONF}"
+                ),
+                "Remove the setting so lookups through resolved see the entries",
+            )
+        });
+    }
+    let processes = running_processes();
+    if processes.iter().any(|p| p == "dnsmasq") {
+        checks.push(Check::warn(
+            NAME,
+            "dnsmasq is running and caches the hosts file",
+            "It only re-reads /etc/hosts on SIGHUP; send one after updates or use its --hostsdir option",
+        ));
+    }
+    if processes.iter().any(|p| p == "nscd") {
+        checks.push(Check::warn(
+            NAME,
+            "nscd is running and may cache host lookups",
+            "Set `enable-cache hosts no` in /etc/nscd.conf, or run `nscd -i hosts` after updates",
+        ));
+    }
+    if checks.is_empty() {
+        checks.push(Check::ok(NAME, "No caching resolver found"));
+    }
+    checks
+}
+
+/// Whether `ReadEtcHosts=` in `conf` is unset or true. The last setting wins,
+/// and any of systemd's spellings of false turns it off.
+fn resolved_reads_etc_hosts(conf: &str) -> bool {
+    conf.lines()
+        .filter_map(|line| line.split_once('='))
+        .rfind(|(key, _)| key.trim() == "ReadEtcHosts")
+        .is_none_or(|(_, value)| {
+            !["no", "false", "off", "0"]
+                .iter()
+                .any(|off| value.trim().eq_ignore_ascii_case(off))
+        })
+}
+
+/// Names of the running processes, from `/proc`. Empty elsewhere.
+fn running_processes() -> Vec<String> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| fs::read_to_string(entry.ok()?.path().join("comm")).ok())
+        .map(|comm| comm.trim().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::indexing_slicing
+)]
+mod tests {
+    use super::*;
+    use crate::hosts_file::{END_TAG, START_TAG};
+
+    #[test]
+    fn test_check_markers() {
+        assert_eq!(
+            check_markers("127.0.0.1 localhost\n", None).status,
+            Status::Ok
+        );
+        let good = format!("{START_TAG}\n172.17.0.2 web.docker\n{END_TAG}\n");
+        assert_eq!(check_markers(&good, None).status, Status::Ok);
+        assert_eq!(
+            check_markers(&format!("{good}{good}"), None).status,
+            Status::Warn
+        );
+        assert_eq!(
+            check_markers(&format!("{START_TAG}\n"), None).status,
+            Status::Warn
+        );
+        // Another instance's section doesn't count
+        assert_eq!(check_markers(&good, Some("shop")).status, Status::Ok);
+    }
+
+    #[test]
+    fn test_check_nsswitch() {
+        assert_eq!(check_nsswitch("hosts: files dns\n").status, Status::Ok);
+        let after =
+            check_nsswitch("# hosts: files\nhosts: mdns4_minimal [NOTFOUND=return] files\n");
+        assert_eq!(after.status, Status::Warn);
+        assert!(
+            after.message.ends_with("mdns4_minimal [NOTFOUND=return]"),
+            "{}",
+            after.message
+        );
+        assert_eq!(check_nsswitch("hosts: dns\n").status, Status::Fail);
+        assert_eq!(check_nsswitch("passwd: files\n").status, Status::Fail);
+    }
+
+    #[test]
+    fn test_mount_source() {
+        let mountinfo = "\
+22 1 8:1 / / rw,relatime - ext4 /dev/sda1 rw
+612 22 8:1 /var/lib/docker/containers/abc/hosts /etc/hosts rw,relatime - ext4 /dev/sda1 rw
+613 22 8:1 /home/me/my\\040hosts /mnt/my\\040hosts rw - ext4 /dev/sda1 rw
+";
+        assert_eq!(
+            mount_source(mountinfo, "/etc/hosts").as_deref(),
+            Some("/var/lib/docker/containers/abc/hosts")
+        );
+        assert_eq!(
+            mount_source(mountinfo, "/mnt/my hosts").as_deref(),
+            Some("/home/me/my hosts")
+        );
+        assert_eq!(mount_source(mountinfo, "/"), None);
+        assert_eq!(mount_source(mountinfo, "/etc/resolv.conf"), None);
+    }
+
+    #[test]
+    fn test_resolved_reads_etc_hosts() {
+        assert!(resolved_reads_etc_hosts("[Resolve]\n#ReadEtcHosts=no\n"));
+        assert!(!resolved_reads_etc_hosts("[Resolve]\nReadEtcHosts = no\n"));
+        for off in ["false", "0", "off", "No"] {
+            assert!(!resolved_reads_etc_hosts(&format!("ReadEtcHosts={off}\n")));
+        }
+        assert!(
+            resolved_reads_etc_hosts("ReadEtcHosts=no\nReadEtcHosts=yes\n"),
+            "the last setting wins"
+        );
+    }
+
+    #[test]
+    fn test_bind_mount_check() {
+        let path = Path::new("/etc/hosts");
+        let source = Some("/etc/hosts".to_string());
+        assert_eq!(bind_mount_check(path, source, true).status, Status::Ok);
+        assert_eq!(
+            bind_mount_check(path, None, true).status,
+            Status::Warn,
+            "the container's own hosts file isn't the host's"
+        );
+        assert_eq!(bind_mount_check(path, None, false).status, Status::Ok);
+    }
+}
@@ -0,0 +1,326 @@
+//! Filters that scope which containers and networks get published.
+
+use bollard::models::ContainerInspectResponse;
+use std::collections::HashMap;
+
+/// Matches `text` against a shell-style glob where `*` matches any run of
+/// characters and `?` matches exactly one. Everything else is literal.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern_chars: Vec<char> = pattern.chars().collect();
+    let text_chars: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` seen and the text position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text_chars.len() {
+        match pattern_chars.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || Some(&c) == text_chars.get(t) => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` swallow one more character and retry
+                Some((star_p, star_t)) => {
+                    backtrack = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern_chars
+        .get(p..)
+        .is_some_and(|rest| rest.iter().all(|&c| c == '*'))
+}
+
+/// Global include/exclude globs for the networks containers are published on.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl NetworkFilter {
+    pub const fn new(include: Vec<String>, exclude: Vec<String>) -> Self {
+        Self { include, exclude }
+    }
+
+    /// A network is allowed when it matches an include glob (or there are none)
+    /// and doesn't match any exclude glob.
+    pub fn allows(&self, network_name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|g| glob_match(g, network_name)))
+            && !self.exclude.iter().any(|g| glob_match(g, network_name))
+    }
+}
+
+/// Keys accepted by `--filter`, with Docker's own meaning.
+const FILTER_KEYS: [&str; 4] = ["label", "name", "network", "ancestor"];
+
+/// Parses a `--filter` argument in Docker's `key=value` syntax.
+pub fn parse_filter(expression: &str) -> Result<(String, String), String> {
+    let (raw_key, value) = expression
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got \"{expression}\""))?;
+    let key = raw_key.trim().to_lowercase();
+    if !FILTER_KEYS.contains(&key.as_str()) {
+        return Err(format!(
+            "unsupported filter \"{key}\", expected one of: {}",
+            FILTER_KEYS.join(", ")
+        ));
+    }
+    if value.is_empty() {
+        return Err(format!("filter \"{key}\" needs a value"));
+    }
+    Ok((key, value.to_string()))
+}
+
+/// Docker-style container filters (`label=...`, `name=...`, `network=...`,
+/// `ancestor=...`). Values of the same key are OR-ed, different keys AND-ed.
+#[derive(Debug, Clone, Default)]
+pub struct ContainerFilter {
+    filters: HashMap<String, Vec<String>>,
+}
+
+impl ContainerFilter {
+    pub fn new(expressions: Vec<(String, String)>) -> Self {
+        let mut filters: HashMap<String, Vec<String>> = HashMap::new();
+        for (key, value) in expressions {
+            filters.entry(key).or_default().push(value);
+        }
+        Self { filters }
+    }
+
+    /// Filters for `list_containers`, which understands the keys as-is.
+    pub fn list_filters(&self) -> HashMap<String, Vec<String>> {
+        self.filters.clone()
+    }
+
+    /// Filters for the container event stream. Only `label` means the same
+    /// there: the `container` event filter matches a name prefix rather than a
+    /// substring, `image` no image ID, and there's no `network` one. Those
+    /// are only checked by [`Self::matches`] after inspecting, otherwise a
+    /// published container's stop events could be filtered out.
+    pub fn event_filters(&self) -> HashMap<String, Vec<String>> {
+        self.filters
+            .iter()
+            .filter(|(key, _)| key.as_str() == "label")
+            .map(|(key, values)| (key.clone(), values.clone()))
+            .collect()
+    }
+
+    /// Checks an inspected container against the filters, so containers that
+    /// slipped through (e.g. via network events) never claim hostnames.
+    /// `name` matches a substring and `ancestor` the image name, with or
+    /// without its tag, or an image ID prefix.
+    pub fn matches(&self, container: &ContainerInspectResponse) -> bool {
+        self.filters.iter().all(|(key, values)| {
+            values
+                .iter()
+                .any(|value| Self::matches_one(container, key, value))
+        })
+    }
+
+    fn matches_one(container: &ContainerInspectResponse, key: &str, value: &str) -> bool {
+        let config = container.config.as_ref();
+        match key {
+            "label" => {
+                let labels = config.and_then(|c| c.labels.as_ref());
+                let (label, expected) = value
+                    .split_once('=')
+                    .map_or((value, None), |(k, v)| (k, Some(v)));
+                labels
+                    .and_then(|l| l.get(label))
+                    .is_some_and(|actual| expected.is_none_or(|e| e == actual))
+            }
+            "name" => container
+                .name
+                .as_deref()
+                .is_some_and(|name| name.trim_start_matches('/').contains(value)),
+            "network" => container
+                .network_settings
+                .as_ref()
+                .and_then(|n| n.networks.as_ref())
+                .is_some_and(|networks| {
+                    networks.iter().any(|(name, endpoint)| {
+                        name == value || endpoint.network_id.as_deref() == Some(value)
+                    })
+                }),
+            "ancestor" => {
+                let image = config.and_then(|c| c.image.as_deref()).unwrap_or_default();
+                let untagged = image.rsplit_once(':').map_or(image, |(repo, _)| repo);
+                image == value
+                    || untagged == value
+                    || container
+                        .image
+                        .as_deref()
+                        .is_some_and(|id| id.trim_start_matches("sha256:").starts_with(value))
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::indexing_slicing
+)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("myapp", "myapp"));
+        assert!(!glob_match("myapp", "myapp2"));
+        assert!(glob_match("*_default", "shop_default"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("shop_*", "shop_"));
+        assert!(glob_match("*back*end*", "shop_backend_internal"));
+        assert!(glob_match("net?", "net1"));
+        assert!(!glob_match("net?", "net"));
+        assert!(!glob_match("*_default", "shop_default_2"));
+    }
+
+    #[test]
+    fn test_network_filter() {
+        let allow_all = NetworkFilter::default();
+        assert!(allow_all.allows("anything"));
+
+        let filter = NetworkFilter::new(
+            vec!["*_default".to_string(), "public".to_string()],
+            vec!["legacy_*".to_string()],
+        );
+        assert!(filter.allows("shop_default"));
+        assert!(filter.allows("public"));
+        assert!(!filter.allows("shop_internal"), "not included");
+        assert!(
+            !filter.allows("legacy_default"),
+            "exclude wins over include"
+        );
+
+        let exclude_only = NetworkFilter::new(vec![], vec!["*_internal".to_string()]);
+        assert!(exclude_only.allows("shop_default"));
+        assert!(!exclude_only.allows("shop_internal"));
+    }
+
+    fn container(
+        name: &str,
+        image: &str,
+        labels: &[(&str, &str)],
+        network: &str,
+    ) -> ContainerInspectResponse {
+        ContainerInspectResponse {
+            name: Some(format!("/{name}")),
+            image: Some("sha256:0123456789abcdef".to_string()),
+            config: Some(bollard::models::ContainerConfig {
+                image: Some(image.to_string()),
+                labels: Some(
+                    labels
+                        .iter()
+                        .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+                        .collect(),
+                ),
+                ..Default::default()
+            }),
+            network_settings: Some(bollard::models::NetworkSettings {
+                networks: Some(HashMap::from([(
+                    network.to_string(),
+                    bollard::models::EndpointSettings::default(),
+                )])),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        assert_eq!(
+            parse_filter("label=com.docker.compose.project=shop"),
+            Ok((
+                "label".to_string(),
+                "com.docker.compose.project=shop".to_string()
+            ))
+        );
+        assert!(parse_filter("name").is_err());
+        assert!(parse_filter("status=running").is_err());
+        assert!(parse_filter("name=").is_err());
+    }
+
+    #[test]
+    fn test_container_filter_matches() {
+        let web = container(
+            "shop-web-1",
+            "nginx:1.27",
+            &[("com.docker.compose.project", "shop")],
+            "shop_default",
+        );
+
+        let matches = |expressions: &[&str]| {
+            ContainerFilter::new(
+                expressions
+                    .iter()
+                    .map(|e| parse_filter(e).unwrap())
+                    .collect(),
+            )
+            .matches(&web)
+        };
+
+        assert!(matches(&[]));
+        assert!(matches(&["label=com.docker.compose.project"]));
+        assert!(matches(&["label=com.docker.compose.project=shop"]));
+        assert!(!matches(&["label=com.docker.compose.project=blog"]));
+        assert!(matches(&["name=web"]));
+        assert!(matches(&["network=shop_default"]));
+        assert!(matches(&["ancestor=nginx"]));
+        assert!(matches(&["ancestor=nginx:1.27"]));
+        assert!(matches(&["ancestor=0123456789ab"]));
+        assert!(!matches(&["ancestor=redis"]));
+        assert!(
+            matches(&["name=api", "name=web"]),
+            "values of the same key are OR-ed"
+        );
+        assert!(
+            !matches(&["name=web", "network=blog_default"]),
+            "different keys are AND-ed"
+        );
+    }
+
+    #[test]
+    fn test_container_filter_event_filters() {
+        let filter = ContainerFilter::new(vec![
+            ("label".to_string(), "tier=web".to_string()),
+            ("name".to_string(), "web".to_string()),
+            ("network".to_string(), "shop_default".to_string()),
+            ("ancestor".to_string(), "nginx".to_string()),
+        ]);
+        let events = filter.event_filters();
+        assert_eq!(
+            events,
+            HashMap::from([("label".to_string(), vec!["tier=web".to_string()])])
+        );
+    }
+
+    #[test]
+    fn test_container_filter_name_substring_gets_events() {
+        // Published at startup, as list_containers matches a substring
+        let web = container("shop-web-1", "nginx:1.27", &[], "shop_default");
+        let filter = ContainerFilter::new(vec![parse_filter("name=web").unwrap()]);
+        assert!(filter.matches(&web));
+
+        // Docker's `container` event filter would only match a "web" prefix,
+        // so its stop events must not be narrowed down by name
+        assert!(
+            filter.event_filters().is_empty(),
+            "{:?}",
+            filter.event_filters()
+        );
+    }
+}
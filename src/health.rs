@@ -0,0 +1,81 @@
+//! Liveness and readiness of the daemon, for the `/healthz` and `/readyz`
+//! endpoints.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+#[derive(Debug, Default)]
+pub struct Health {
+    /// Following the Docker event stream, i.e. not waiting to reconnect.
+    event_stream: AtomicBool,
+    /// The initial synchronization completed.
+    synchronized: AtomicBool,
+    /// Error of the last write, if it failed.
+    write_error: Mutex<Option<String>>,
+}
+
+impl Health {
+    pub fn set_event_stream(&self, connected: bool) {
+        self.event_stream.store(connected, Ordering::Relaxed);
+    }
+
+    pub fn set_synchronized(&self) {
+        self.synchronized.store(true, Ordering::Relaxed);
+    }
+
+    pub fn record_write(&self, result: &anyhow::Result<()>) {
+        if let Ok(mut write_error) = self.write_error.lock() {
+            *write_error = result.as_ref().err().map(|e| format!("{e:#}"));
+        }
+    }
+
+    /// Alive as long as the event stream is connected. Returns why not otherwise.
+    pub fn liveness(&self) -> Result<(), String> {
+        if self.event_stream.load(Ordering::Relaxed) {
+            Ok(())
+        } else {
+            Err("Docker event stream not connected".to_string())
+        }
+    }
+
+    /// Ready once the initial synchronization completed, as long as the last
+    /// write succeeded. Returns why not otherwise.
+    pub fn readiness(&self) -> Result<(), String> {
+        if !self.synchronized.load(Ordering::Relaxed) {
+            return Err("initial synchronization not completed".to_string());
+        }
+        let write_error = self.write_error.lock().ok().and_then(|e| e.clone());
+        write_error.map_or(Ok(()), |error| Err(format!("last write failed: {error}")))
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::indexing_slicing
+)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readiness() {
+        let health = Health::default();
+        assert!(health.liveness().is_err(), "not connected yet");
+        assert!(health.readiness().is_err(), "not synchronized yet");
+
+        health.set_event_stream(true);
+        health.set_synchronized();
+        assert_eq!(health.liveness(), Ok(()));
+        assert_eq!(health.readiness(), Ok(()));
+
+        health.record_write(&Err(anyhow::anyhow!("locked")));
+        assert_eq!(
+            health.readiness(),
+            Err("last write failed: locked".to_string())
+        );
+        health.record_write(&Ok(()));
+        assert_eq!(health.readiness(), Ok(()));
+    }
+}
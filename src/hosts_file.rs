@@ -0,0 +1,663 @@
+//! Reading and splicing the managed section of a hosts file.
+
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::fs::{File, TryLockError};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+use tracing::warn;
+
+use crate::backup::Backups;
+
+pub const START_TAG: &str = "## docker-hostmanager-start";
+pub const END_TAG: &str = "## docker-hostmanager-end";
+
+/// The marker lines around one instance's managed section. The default
+/// instance uses the bare tags, others append `:<id>`, so several instances
+/// can share a file without touching each other's sections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Markers {
+    pub start: String,
+    pub end: String,
+}
+
+impl Default for Markers {
+    fn default() -> Self {
+        Self {
+            start: START_TAG.to_string(),
+            end: END_TAG.to_string(),
+        }
+    }
+}
+
+impl Markers {
+    pub fn for_instance(instance: &str) -> Self {
+        Self {
+            start: format!("{START_TAG}:{instance}"),
+            end: format!("{END_TAG}:{instance}"),
+        }
+    }
+}
+
+/// Validates an `--instance` ID, which ends up in the marker lines.
+pub fn parse_instance(id: &str) -> Result<String, String> {
+    if id.is_empty() {
+        return Err("instance ID can't be empty".to_string());
+    }
+    if let Some(c) = id
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+    {
+        return Err(format!(
+            "invalid character '{c}' in instance ID, expected letters, digits, '-', '_' or '.'"
+        ));
+    }
+    Ok(id.to_string())
+}
+
+/// A managed section found in a hosts file, as shown by `list-sections`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Section {
+    /// `None` for the default instance.
+    pub instance: Option<String>,
+    /// 1-based line of the start marker, `None` for a stray end marker.
+    pub start_line: Option<usize>,
+    /// 1-based line of the end marker, `None` while unterminated.
+    pub end_line: Option<usize>,
+    /// Host entries in the section (comment and blank lines aren't counted).
+    pub entries: usize,
+}
+
+/// If `line` is a marker built from `tag`, returns its instance ID, which
+/// is empty for the default instance.
+fn marker_instance<'a>(line: &'a str, tag: &str) -> Option<&'a str> {
+    let rest = line.trim().strip_prefix(tag)?;
+    if rest.is_empty() {
+        return Some(rest);
+    }
+    rest.strip_prefix(':').filter(|id| !id.is_empty())
+}
+
+fn instance_name(id: &str) -> Option<String> {
+    (!id.is_empty()).then(|| id.to_string())
+}
+
+/// Lists every instance's managed sections in `content`, in file order.
+pub fn sections(content: &str) -> Vec<Section> {
+    let mut found: Vec<Section> = Vec::new();
+    // Index in `found` of the open section of each instance
+    let mut open: HashMap<Option<String>, usize> = HashMap::new();
+
+    for (index, line) in content.lines().enumerate() {
+        let line_number = index + 1;
+        if let Some(id) = marker_instance(line, START_TAG) {
+            let instance = instance_name(id);
+            open.insert(instance.clone(), found.len());
+            found.push(Section {
+                instance,
+                start_line: Some(line_number),
+                end_line: None,
+                entries: 0,
+            });
+        } else if let Some(id) = marker_instance(line, END_TAG) {
+            let instance = instance_name(id);
+            match open.remove(&instance).and_then(|i| found.get_mut(i)) {
+                Some(section) => section.end_line = Some(line_number),
+                None => found.push(Section {
+                    instance,
+                    start_line: None,
+                    end_line: Some(line_number),
+                    entries: 0,
+                }),
+            }
+        } else {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            for &i in open.values() {
+                if let Some(section) = found.get_mut(i) {
+                    section.entries += 1;
+                }
+            }
+        }
+    }
+    found
+}
+
+const BOM: &str = "\u{feff}";
+
+/// Formatting details of an existing file that are kept when rewriting it,
+/// e.g. for a Windows hosts file edited through WSL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Format {
+    bom: bool,
+    crlf: bool,
+    final_newline: bool,
+}
+
+impl Format {
+    /// Uses the line ending most lines have for the whole file, so a single
+    /// line edited with another tool doesn't decide it. An empty file gets
+    /// `\n` endings and a final newline.
+    fn detect(content: &str) -> Self {
+        let newlines = content.matches('\n').count();
+        let crlfs = content.matches("\r\n").count();
+        Self {
+            bom: content.starts_with(BOM),
+            crlf: crlfs > newlines - crlfs,
+            final_newline: content.is_empty() || content.ends_with('\n'),
+        }
+    }
+
+    fn apply(self, lines: &[&str]) -> String {
+        let newline = if self.crlf { "\r\n" } else { "\n" };
+        let mut content = String::new();
+        if self.bom {
+            content.push_str(BOM);
+        }
+        content.push_str(&lines.join(newline));
+        if self.final_newline {
+            content.push_str(newline);
+        }
+        content
+    }
+}
+
+/// The result of replacing the managed section of a hosts file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Spliced {
+    pub content: String,
+    /// Stray, duplicated or unterminated markers were found and cleaned up.
+    pub repaired: bool,
+}
+
+/// Replaces the managed section delimited by `markers` with `entries`,
+/// keeping every other line, including other instances' sections. The
+/// section stays where the first marker was found, or is appended when there
+/// is none. With no entries the section is removed.
+///
+/// Malformed markers are repaired rather than left behind: an end marker
+/// without a start is dropped, extra sections are merged into the first one,
+/// and a start marker without an end is dropped while the lines after it are
+/// kept, since they may not be ours.
+pub fn splice(original: &str, entries: &[String], markers: &Markers) -> Spliced {
+    let format = Format::detect(original);
+    let content = original.strip_prefix(BOM).unwrap_or(original);
+    let mut lines: Vec<&str> = Vec::new();
+    let mut insert_at = None;
+    let mut repaired = false;
+    // Lines of the section currently being read, until its end marker shows up
+    let mut section: Option<Vec<&str>> = None;
+
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if trimmed == markers.start {
+            if section.is_some() {
+                // A nested start: the previous one was never closed
+                repaired = true;
+            } else {
+                if insert_at.is_some() {
+                    repaired = true;
+                }
+                section = Some(Vec::new());
+            }
+            insert_at.get_or_insert(lines.len());
+        } else if trimmed == markers.end {
+            if section.take().is_none() {
+                repaired = true;
+                insert_at.get_or_insert(lines.len());
+            }
+        } else if let Some(section_lines) = section.as_mut() {
+            section_lines.push(line);
+        } else {
+            lines.push(line);
+        }
+    }
+    if let Some(unterminated) = section {
+        repaired = true;
+        lines.extend(unterminated);
+    }
+
+    let mut block = Vec::new();
+    if !entries.is_empty() {
+        block.push(markers.start.as_str());
+        block.extend(entries.iter().map(String::as_str));
+        block.push(markers.end.as_str());
+    }
+
+    if let Some(position) = insert_at {
+        lines.splice(position..position, block);
+    } else if !block.is_empty() {
+        // Add a blank line before our section if the file doesn't end with one
+        if lines.last().is_some_and(|last| !last.is_empty()) {
+            lines.push("");
+        }
+        lines.extend(block);
+    }
+
+    Spliced {
+        content: format.apply(&lines),
+        repaired,
+    }
+}
+
+/// How often a contended lock is retried.
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A hosts file opened with an exclusive advisory lock (`flock` on Unix,
+/// `LockFileEx` on Windows), held until it is dropped. Other tools editing the
+/// file can't interleave with a read-splice-write cycle done through it, as
+/// long as they lock it too.
+pub struct LockedFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl LockedFile {
+    /// Opens and locks `path`, retrying while another process holds the lock
+    /// and giving up after `timeout`.
+    pub async fn open(path: &Path, timeout: Duration) -> Result<Self> {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+
+        let started = Instant::now();
+        let mut contended = false;
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) => {
+                    if started.elapsed() >= timeout {
+                        bail!(
+                            "{} is locked by another process; gave up after {}ms",
+                            path.display(),
+                            timeout.as_millis()
+                        );
+                    }
+                    if !contended {
+                        contended = true;
+                        warn!(
+                            "{} is locked by another process, waiting up to {}ms",
+                            path.display(),
+                            timeout.as_millis()
+                        );
+                    }
+                    sleep(LOCK_RETRY_INTERVAL).await;
+                }
+                Err(TryLockError::Error(e)) => {
+                    return Err(e).with_context(|| format!("Failed to lock {}", path.display()));
+                }
+            }
+        }
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn read(&mut self) -> Result<String> {
+        let mut content = String::new();
+        self.file
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| self.file.read_to_string(&mut content))
+            .with_context(|| format!("Failed to read {}", self.path.display()))?;
+        Ok(content)
+    }
+
+    /// Replaces the contents in place, through the locked handle, so the lock
+    /// stays effective and bind-mounted files keep their inode.
+    pub fn write(&mut self, content: &str) -> Result<()> {
+        self.file
+            .set_len(0)
+            .and_then(|()| self.file.seek(SeekFrom::Start(0)))
+            .and_then(|_| self.file.write_all(content.as_bytes()))
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+/// Removes the managed section delimited by `markers` from the file at
+/// `path`, leaving everything else as is, and trying to back the file up
+/// first when `backups` are given. Returns `false` if there was nothing to remove.
+pub async fn remove_section(
+    path: &Path,
+    markers: &Markers,
+    backups: Option<&Backups>,
+    lock_timeout: Duration,
+) -> Result<bool> {
+    let mut file = LockedFile::open(path, lock_timeout).await?;
+    let content = file.read()?;
+    let spliced = splice(&content, &[], markers);
+    if spliced.content == content {
+        return Ok(false);
+    }
+    // As for writes, a backup that can't be saved doesn't stop the cleanup
+    if let Some(Err(e)) = backups.map(|b| b.save(&content)) {
+        warn!("{e:#}, removing the section without a backup");
+    }
+    file.write(&spliced.content)?;
+    Ok(true)
+}
+
+/// Fingerprint of the managed section delimited by `markers`: the markers and
+/// the lines between them. Stray or duplicated markers change it too, so
+/// comparing it with the fingerprint of the last write detects any edit to
+/// our lines. A BOM is not part of the section, and doesn't change it.
+pub fn section_checksum(content: &str, markers: &Markers) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let mut inside = false;
+    for line in content.strip_prefix(BOM).unwrap_or(content).lines() {
+        let trimmed = line.trim();
+        if trimmed == markers.start || trimmed == markers.end {
+            inside = trimmed == markers.start;
+            line.hash(&mut hasher);
+        } else if inside {
+            line.hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+/// Renders a unified diff between two versions of the hosts file at `path`,
+/// or an empty string when they are the same.
+pub fn unified_diff(old: &str, new: &str, path: &Path) -> String {
+    if old == new {
+        return String::new();
+    }
+    let name = path.display().to_string();
+    similar::TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(&name, &name)
+        .to_string()
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::indexing_slicing
+)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn entries() -> Vec<String> {
+        vec!["172.17.0.2 web.docker".to_string()]
+    }
+
+    #[test]
+    fn test_splice_replaces_section_in_place() {
+        let content =
+            format!("127.0.0.1 localhost\n{START_TAG}\n172.17.0.9 old\n{END_TAG}\n10.0.0.1 nas\n");
+        assert_eq!(
+            splice(&content, &entries(), &Markers::default()),
+            Spliced {
+                content: format!(
+                    "127.0.0.1 localhost\n{START_TAG}\n172.17.0.2 web.docker\n{END_TAG}\n10.0.0.1 nas\n"
+                ),
+                repaired: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_splice_keeps_line_endings_and_bom() {
+        let windows = format!(
+            "\u{feff}127.0.0.1 localhost\r\n{START_TAG}\r\n172.17.0.9 old\r\n{END_TAG}\r\n"
+        );
+        assert_eq!(
+            splice(&windows, &entries(), &Markers::default()).content,
+            format!(
+                "\u{feff}127.0.0.1 localhost\r\n{START_TAG}\r\n172.17.0.2 web.docker\r\n{END_TAG}\r\n"
+            )
+        );
+
+        assert_eq!(
+            splice("127.0.0.1 localhost", &entries(), &Markers::default()).content,
+            format!("127.0.0.1 localhost\n\n{START_TAG}\n172.17.0.2 web.docker\n{END_TAG}"),
+            "a file without a final newline doesn't get one"
+        );
+        assert_eq!(
+            splice("", &entries(), &Markers::default()).content,
+            format!("{START_TAG}\n172.17.0.2 web.docker\n{END_TAG}\n")
+        );
+
+        let mostly_crlf = "# added with echo\n127.0.0.1 localhost\r\n::1 localhost\r\n";
+        assert_eq!(
+            splice(mostly_crlf, &entries(), &Markers::default()).content,
+            format!(
+                "# added with echo\r\n127.0.0.1 localhost\r\n::1 localhost\r\n\r\n\
+                 {START_TAG}\r\n172.17.0.2 web.docker\r\n{END_TAG}\r\n"
+            ),
+            "the line ending of most lines wins over the first line's"
+        );
+    }
+
+    #[test]
+    fn test_splice_appends_and_removes() {
+        let appended = splice("127.0.0.1 localhost\n", &entries(), &Markers::default());
+        assert_eq!(
+            appended.content,
+            format!("127.0.0.1 localhost\n\n{START_TAG}\n172.17.0.2 web.docker\n{END_TAG}\n")
+        );
+
+        let removed = splice(&appended.content, &[], &Markers::default());
+        assert_eq!(removed.content, "127.0.0.1 localhost\n\n");
+        assert!(!removed.repaired);
+    }
+
+    #[test]
+    fn test_splice_repairs_end_before_start() {
+        let content =
+            format!("127.0.0.1 localhost\n{END_TAG}\n10.0.0.1 nas\n{START_TAG}\n172.17.0.9 old\n");
+        let spliced = splice(&content, &entries(), &Markers::default());
+        assert!(spliced.repaired);
+        assert_eq!(
+            spliced.content,
+            format!(
+                "127.0.0.1 localhost\n{START_TAG}\n172.17.0.2 web.docker\n{END_TAG}\n10.0.0.1 nas\n172.17.0.9 old\n"
+            ),
+            "the stray end marker is dropped and the unterminated start keeps the lines after it"
+        );
+    }
+
+    #[test]
+    fn test_splice_merges_duplicate_sections() {
+        let content = format!(
+            "{START_TAG}\n172.17.0.9 old\n{END_TAG}\n10.0.0.1 nas\n{START_TAG}\n172.17.0.8 older\n{END_TAG}\n"
+        );
+        let spliced = splice(&content, &entries(), &Markers::default());
+        assert!(spliced.repaired);
+        assert_eq!(
+            spliced.content,
+            format!("{START_TAG}\n172.17.0.2 web.docker\n{END_TAG}\n10.0.0.1 nas\n")
+        );
+        assert_eq!(
+            spliced.content.matches(START_TAG).count(),
+            1,
+            "only one section must remain"
+        );
+    }
+
+    #[test]
+    fn test_section_checksum() {
+        let checksum = |content: &str| section_checksum(content, &Markers::default());
+        let clean = splice("127.0.0.1 localhost\n", &entries(), &Markers::default()).content;
+        assert_eq!(
+            checksum(&clean),
+            checksum(&format!("# other lines\n{clean}10.0.0.1 nas\n")),
+            "lines outside the section don't matter"
+        );
+        assert_ne!(
+            checksum(&clean),
+            checksum(&clean.replace("172.17.0.2", "172.17.0.3"))
+        );
+        assert_ne!(
+            checksum(&clean),
+            checksum(&clean.replace(END_TAG, "")),
+            "a removed marker is an edit"
+        );
+        assert_ne!(
+            checksum(&clean),
+            checksum(&format!("{clean}{END_TAG}\n")),
+            "a duplicated marker is an edit"
+        );
+        assert_ne!(checksum(&clean), checksum(""));
+
+        let section_first = splice("", &entries(), &Markers::default()).content;
+        assert_eq!(
+            checksum(&section_first),
+            checksum(&format!("{BOM}{section_first}")),
+            "a BOM before the start marker is not an edit"
+        );
+    }
+
+    #[test]
+    fn test_splice_leaves_other_instances_alone() {
+        let shop = Markers::for_instance("shop");
+        assert_eq!(shop.start, format!("{START_TAG}:shop"));
+
+        let content = format!(
+            "{START_TAG}\n172.17.0.9 default.docker\n{END_TAG}\n{START_TAG}:blog\n172.18.0.2 blog.docker\n{END_TAG}:blog\n"
+        );
+        let spliced = splice(&content, &entries(), &shop);
+        assert!(
+            !spliced.repaired,
+            "other instances' markers aren't ours to repair"
+        );
+        assert_eq!(
+            spliced.content,
+            format!("{content}\n{START_TAG}:shop\n172.17.0.2 web.docker\n{END_TAG}:shop\n")
+        );
+
+        let removed = splice(&content, &[], &Markers::default());
+        assert_eq!(
+            removed.content,
+            format!("{START_TAG}:blog\n172.18.0.2 blog.docker\n{END_TAG}:blog\n")
+        );
+    }
+
+    #[test]
+    fn test_parse_instance() {
+        assert_eq!(parse_instance("shop-1.dev"), Ok("shop-1.dev".to_string()));
+        assert!(parse_instance("").is_err());
+        assert!(parse_instance("my shop").is_err());
+        assert!(parse_instance("a:b").is_err());
+    }
+
+    #[test]
+    fn test_sections() {
+        let content = format!(
+            "127.0.0.1 localhost\n{START_TAG}\n# shop\n172.17.0.2 web.docker\n172.17.0.3 db.docker\n{END_TAG}\n\
+             {START_TAG}:blog\n172.18.0.2 blog.docker\n{END_TAG}:shop\n"
+        );
+        assert_eq!(
+            sections(&content),
+            vec![
+                Section {
+                    instance: None,
+                    start_line: Some(2),
+                    end_line: Some(6),
+                    entries: 2,
+                },
+                Section {
+                    instance: Some("blog".to_string()),
+                    start_line: Some(7),
+                    end_line: None,
+                    entries: 1,
+                },
+                Section {
+                    instance: Some("shop".to_string()),
+                    start_line: None,
+                    end_line: Some(9),
+                    entries: 0,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_section() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hosts");
+        let shop = format!("{START_TAG}:shop\n172.18.0.2 shop.docker\n{END_TAG}:shop\n");
+        fs::write(
+            &path,
+            format!("127.0.0.1 localhost\n{START_TAG}\n172.17.0.2 web.docker\n{END_TAG}\n{shop}"),
+        )
+        .unwrap();
+
+        assert!(
+            remove_section(&path, &Markers::default(), None, Duration::from_secs(1))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("127.0.0.1 localhost\n{shop}")
+        );
+        assert!(
+            !remove_section(&path, &Markers::default(), None, Duration::from_secs(1))
+                .await
+                .unwrap(),
+            "nothing left to remove"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_locked_file_waits_for_other_locks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hosts");
+        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+        // Another program holding the lock
+        let other = File::open(&path).unwrap();
+        other.lock().unwrap();
+        let error = LockedFile::open(&path, Duration::from_millis(100))
+            .await
+            .err()
+            .expect("the lock is held elsewhere");
+        assert!(
+            error.to_string().contains("locked by another process"),
+            "unexpected error: {error}"
+        );
+
+        // Released while waiting
+        let release = async {
+            sleep(Duration::from_millis(100)).await;
+            other.unlock().unwrap();
+        };
+        let (locked, ()) = tokio::join!(LockedFile::open(&path, Duration::from_secs(5)), release);
+        let mut file = locked.unwrap();
+        assert_eq!(file.read().unwrap(), "127.0.0.1 localhost\n");
+        file.write("10.0.0.1 nas\n").unwrap();
+        assert_eq!(file.read().unwrap(), "10.0.0.1 nas\n");
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let path = Path::new("/etc/hosts");
+        let old = "127.0.0.1 localhost\n";
+        assert_eq!(unified_diff(old, old, path), "");
+
+        let spliced = splice(
+            old,
+            &["172.17.0.2 web.docker".to_string()],
+            &Markers::default(),
+        );
+        let diff = unified_diff(old, &spliced.content, path);
+        assert!(
+            diff.starts_with("--- /etc/hosts\n+++ /etc/hosts\n@@"),
+            "{diff}"
+        );
+        assert!(diff.contains("\n+172.17.0.2 web.docker\n"), "{diff}");
+        assert!(diff.contains("\n 127.0.0.1 localhost\n"), "{diff}");
+    }
+}
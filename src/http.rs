@@ -0,0 +1,233 @@
+//! A minimal HTTP/1.1 server for the metrics and health endpoints, and the
+//! client behind the `healthcheck` command. Each connection serves a single
+//! request and is closed.
+
+use anyhow::{Context, Result};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Duration};
+use tracing::{debug, info, warn};
+
+use crate::health::Health;
+use crate::metrics::Metrics;
+
+/// Requests larger than this are rejected; ours have no body.
+const MAX_REQUEST_SIZE: usize = 8192;
+/// Time a client gets to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn text(status: &'static str, body: impl Into<String>) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into(),
+        }
+    }
+}
+
+/// Serves `/metrics`, `/healthz` and `/readyz` on `addr` until the future is
+/// dropped.
+pub async fn serve(addr: SocketAddr, metrics: Arc<Metrics>, health: Arc<Health>) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {addr}"))?;
+    info!("Serving metrics on http://{}/metrics", addr);
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        let connection_metrics = Arc::clone(&metrics);
+        let connection_health = Arc::clone(&health);
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, &connection_metrics, &connection_health).await {
+                debug!("Request from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle(mut stream: TcpStream, metrics: &Metrics, health: &Health) -> Result<()> {
+    let response = match timeout(READ_TIMEOUT, read_request_line(&mut stream)).await {
+        Ok(Ok(Some(line))) => route(&line, metrics, health),
+        Ok(Ok(None)) => Response::text("400 Bad Request", "Bad request\n"),
+        Ok(Err(e)) => return Err(e),
+        Err(_) => Response::text("408 Request Timeout", "Request timeout\n"),
+    };
+
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Reads the request head and returns its first line, or `None` when it is
+/// malformed or too large.
+async fn read_request_line(stream: &mut TcpStream) -> Result<Option<String>> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 1024];
+    while !buffer.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 || buffer.len() + read > MAX_REQUEST_SIZE {
+            return Ok(None);
+        }
+        buffer.extend(chunk.iter().take(read));
+    }
+    Ok(String::from_utf8_lossy(&buffer)
+        .lines()
+        .next()
+        .map(str::to_string))
+}
+
+fn route(request_line: &str, metrics: &Metrics, health: &Health) -> Response {
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Response::text("400 Bad Request", "Bad request\n");
+    };
+    let path = target.split('?').next().unwrap_or(target);
+
+    match (method, path) {
+        ("GET", "/metrics") => Response {
+            status: "200 OK",
+            content_type: "text/plain; version=0.0.4; charset=utf-8",
+            body: metrics.to_string(),
+        },
+        ("GET", "/healthz") => probe(health.liveness()),
+        ("GET", "/readyz") => probe(health.readiness()),
+        (_, "/metrics" | "/healthz" | "/readyz") => {
+            Response::text("405 Method Not Allowed", "Method not allowed\n")
+        }
+        _ => Response::text("404 Not Found", "Not found\n"),
+    }
+}
+
+fn probe(result: Result<(), String>) -> Response {
+    match result {
+        Ok(()) => Response::text("200 OK", "ok\n"),
+        Err(reason) => Response::text("503 Service Unavailable", format!("{reason}\n")),
+    }
+}
+
+/// Requests `path` from the server at `addr` and returns the status code and
+/// body of the response.
+pub async fn get(addr: SocketAddr, path: &str) -> Result<(u16, String)> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(addr).await?;
+        stream
+            .write_all(
+                format!("GET {path} HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n\r\n")
+                    .as_bytes(),
+            )
+            .await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        anyhow::Ok(response)
+    };
+    let response = timeout(READ_TIMEOUT, exchange)
+        .await
+        .with_context(|| format!("No response from {addr} within {}s", READ_TIMEOUT.as_secs()))?
+        .with_context(|| format!("Failed to request http://{addr}{path}"))?;
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .with_context(|| format!("Malformed response from {addr}"))?;
+    Ok((status, body.to_string()))
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::indexing_slicing
+)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_serves_metrics_and_health() {
+        let metrics = Arc::new(Metrics::default());
+        metrics.record_event("container", "start");
+        let health = Arc::new(Health::default());
+
+        // Find a free port, then serve on it
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let server = tokio::spawn(serve(addr, Arc::clone(&metrics), Arc::clone(&health)));
+
+        // Wait for the server to start
+        let mut started = false;
+        for _ in 0..50 {
+            if TcpStream::connect(addr).await.is_ok() {
+                started = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(started, "server didn't start");
+
+        let (status, body) = get(addr, "/metrics").await.unwrap();
+        assert_eq!(status, 200);
+        assert!(
+            body.contains("docker_hostmanager_events_total{type=\"container\",action=\"start\"} 1"),
+            "{body}"
+        );
+        assert_eq!(get(addr, "/other").await.unwrap().0, 404);
+
+        assert_eq!(
+            get(addr, "/readyz").await.unwrap(),
+            (503, "initial synchronization not completed\n".to_string())
+        );
+        health.set_event_stream(true);
+        health.set_synchronized();
+        assert_eq!(
+            get(addr, "/healthz").await.unwrap(),
+            (200, "ok\n".to_string())
+        );
+        assert_eq!(get(addr, "/readyz").await.unwrap().0, 200);
+        server.abort();
+    }
+
+    #[test]
+    fn test_route() {
+        let (metrics, health) = (Metrics::default(), Health::default());
+        assert_eq!(
+            route("GET /metrics?x=1 HTTP/1.1", &metrics, &health).status,
+            "200 OK"
+        );
+        assert_eq!(
+            route("POST /readyz HTTP/1.1", &metrics, &health).status,
+            "405 Method Not Allowed"
+        );
+        assert_eq!(
+            route("GET /healthz HTTP/1.1", &metrics, &health).status,
+            "503 Service Unavailable"
+        );
+        assert_eq!(route("GET", &metrics, &health).status, "400 Bad Request");
+    }
+}
@@ -0,0 +1,85 @@
+//! Log output: the format of tracing events, and whether to color them.
+
+use std::ffi::OsStr;
+use std::io::IsTerminal;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+
+/// How log lines, including the status lines of watch and sync, are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// One line per event, with its fields appended
+    #[default]
+    Compact,
+    /// Multi-line, with each field on a line of its own
+    Pretty,
+    /// One JSON object per event, with its fields at the top level
+    Json,
+}
+
+/// Whether to color output to `stream`: only on a terminal, and unless
+/// `NO_COLOR` is set to a non-empty value (<https://no-color.org>).
+pub fn use_color(stream: &impl IsTerminal) -> bool {
+    color_enabled(
+        std::env::var_os("NO_COLOR").as_deref(),
+        stream.is_terminal(),
+    )
+}
+
+fn color_enabled(no_color: Option<&OsStr>, terminal: bool) -> bool {
+    terminal && no_color.is_none_or(OsStr::is_empty)
+}
+
+/// Installs the global subscriber, logging to stderr when stdout is reserved
+/// for machine-readable output.
+pub fn init(format: LogFormat, verbose: bool, to_stderr: bool) {
+    let filter = if verbose {
+        "docker_hostmanager=debug,bollard=info"
+    } else {
+        "docker_hostmanager=info,bollard=warn"
+    };
+    let (writer, ansi) = if to_stderr {
+        (
+            BoxMakeWriter::new(std::io::stderr),
+            use_color(&std::io::stderr()),
+        )
+    } else {
+        (
+            BoxMakeWriter::new(std::io::stdout),
+            use_color(&std::io::stdout()),
+        )
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_target(false)
+        .with_writer(writer);
+    match format {
+        LogFormat::Compact => builder.compact().with_ansi(ansi).init(),
+        LogFormat::Pretty => builder.pretty().with_ansi(ansi).init(),
+        LogFormat::Json => builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_ansi(false)
+            .init(),
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::indexing_slicing
+)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_enabled() {
+        assert!(color_enabled(None, true));
+        assert!(color_enabled(Some(OsStr::new("")), true), "empty is unset");
+        assert!(!color_enabled(Some(OsStr::new("1")), true));
+        assert!(!color_enabled(None, false), "not a terminal");
+    }
+}
@@ -1,14 +1,36 @@
 use anyhow::{Context, Result};
 use bollard::Docker;
-use clap::{Parser, Subcommand};
+use clap::parser::ValueSource;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand};
 use colored::Colorize;
-use std::path::PathBuf;
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
 use tokio::signal;
+use tracing::{info, warn};
 
+mod backup;
+mod doctor;
+mod filter;
+mod health;
+mod hosts_file;
+mod http;
+mod logging;
+mod metrics;
+mod output;
 mod synchronizer;
+mod systemd;
+mod template;
 mod types;
 
-use synchronizer::Synchronizer;
+use backup::{BackupWhen, Backups};
+use filter::{ContainerFilter, NetworkFilter};
+use hosts_file::Markers;
+use logging::LogFormat;
+use output::OutputFormat;
+use synchronizer::{Candidate, ContainerStatus, GroupBy, Skip, Synchronizer, Verdict};
 
 // Version from git tag at build time
 const VERSION: &str = env!("GIT_VERSION");
@@ -44,6 +66,127 @@ struct Args {
     #[arg(long, env = "DEBOUNCE_MS", default_value = "100", global = true)]
     debounce_ms: u64,
 
+    /// Time allowed for flushing pending writes (or cleaning up) on shutdown, in milliseconds
+    #[arg(
+        long,
+        env = "SHUTDOWN_TIMEOUT_MS",
+        default_value = "5000",
+        global = true
+    )]
+    shutdown_timeout_ms: u64,
+
+    /// How long to wait for other programs to release their lock on the hosts file, in milliseconds
+    #[arg(long, env = "LOCK_TIMEOUT_MS", default_value = "2000", global = true)]
+    lock_timeout_ms: u64,
+
+    /// Also write a `gateway.<network>` entry for each network's gateway address
+    #[arg(long, env = "GATEWAY_ENTRIES", global = true)]
+    gateway_entries: bool,
+
+    /// Hostname added to the gateway entries so the host is reachable by name (empty to disable)
+    #[arg(
+        long,
+        env = "HOST_ALIAS",
+        default_value = "host.docker.internal",
+        global = true
+    )]
+    host_alias: String,
+
+    /// Only publish entries for networks matching this glob (repeatable)
+    #[arg(
+        long = "include-network",
+        value_name = "GLOB",
+        env = "INCLUDE_NETWORKS",
+        value_delimiter = ',',
+        global = true
+    )]
+    include_networks: Vec<String>,
+
+    /// Never publish entries for networks matching this glob (repeatable)
+    #[arg(
+        long = "exclude-network",
+        value_name = "GLOB",
+        env = "EXCLUDE_NETWORKS",
+        value_delimiter = ',',
+        global = true
+    )]
+    exclude_networks: Vec<String>,
+
+    /// Only manage containers matching a Docker filter: label=..., name=..., network=... or ancestor=... (repeatable)
+    #[arg(
+        long = "filter",
+        value_name = "KEY=VALUE",
+        value_parser = filter::parse_filter,
+        global = true
+    )]
+    filters: Vec<(String, String)>,
+
+    /// Group entries in the managed section under per-project or per-network headers
+    #[arg(
+        long,
+        value_enum,
+        env = "GROUP_BY",
+        default_value_t = GroupBy::None,
+        global = true
+    )]
+    group_by: GroupBy,
+
+    /// Annotate each entry with a trailing `# container-name (short id)` comment
+    #[arg(long, env = "ANNOTATE", global = true)]
+    annotate: bool,
+
+    /// Manage the `## docker-hostmanager-start:<ID>` section, so several instances can share a hosts file
+    #[arg(
+        long,
+        value_name = "ID",
+        env = "INSTANCE",
+        value_parser = hosts_file::parse_instance,
+        global = true
+    )]
+    instance: Option<String>,
+
+    /// Number of hosts file backups to keep (0 disables backups)
+    #[arg(long, env = "BACKUP_KEEP", default_value = "5", global = true)]
+    backup_keep: usize,
+
+    /// Directory for hosts file backups (default: next to the hosts file)
+    #[arg(long, value_name = "DIR", env = "BACKUP_DIR", global = true)]
+    backup_dir: Option<PathBuf>,
+
+    /// When to back up the hosts file: before the first write of a session, or before every write
+    #[arg(
+        long,
+        value_enum,
+        env = "BACKUP_WHEN",
+        default_value_t = BackupWhen::FirstWrite,
+        global = true
+    )]
+    backup_when: BackupWhen,
+
+    /// Serve Prometheus metrics and the /healthz and /readyz endpoints on this address while watching or syncing, e.g. 127.0.0.1:9101
+    #[arg(long, value_name = "ADDR", env = "METRICS_ADDR", global = true)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Output format of watch and sync: human-oriented text, or one JSON object per event (logs go to stderr)
+    #[arg(
+        long,
+        value_enum,
+        env = "OUTPUT",
+        default_value_t = OutputFormat::Text,
+        global = true
+    )]
+    output: OutputFormat,
+
+    /// Log format: one line per event, multi-line, or one JSON object per event for log shippers. Colors are off when `NO_COLOR` is set or the output isn't a terminal
+    #[arg(
+        long,
+        value_enum,
+        env = "LOG_FORMAT",
+        default_value_t = LogFormat::Compact,
+        global = true
+    )]
+    log_format: LogFormat,
+
     /// Verbose mode
     #[arg(short, long, global = true)]
     verbose: bool,
@@ -66,40 +209,142 @@ enum Commands {
         /// Run once and exit (don't listen for events)
         #[arg(long)]
         once: bool,
+
+        /// Remove the managed section from the hosts file on SIGINT/SIGTERM
+        #[arg(long, env = "CLEANUP_ON_EXIT")]
+        cleanup_on_exit: bool,
+
+        /// Print a unified diff of the changes instead of writing them; exits with 1 if there are any
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Remove the managed section from a hosts file (doesn't need Docker)
+    Clean {
+        /// Path to the hosts file to clean
+        #[arg(value_name = "HOSTS_FILE")]
+        hosts_file: PathBuf,
+    },
+    /// List the backups of a hosts file, or restore one of them
+    Restore {
+        /// Path to the hosts file to restore
+        #[arg(value_name = "HOSTS_FILE")]
+        hosts_file: PathBuf,
+
+        /// Backup to restore: its number in the list (1 is the newest) or its path
+        #[arg(value_name = "BACKUP")]
+        backup: Option<String>,
+    },
+    /// Show the managed sections in a hosts file and which instance owns each
+    ListSections {
+        /// Path to the hosts file to inspect
+        #[arg(value_name = "HOSTS_FILE", default_value = "/etc/hosts")]
+        hosts_file: PathBuf,
+    },
+    /// Show every active container, the hostnames it owns and the ones it doesn't get
+    Status,
+    /// Explain which containers generate a hostname, through which rules, and which one gets it
+    Resolve {
+        /// Hostname to look up, e.g. api.myapp
+        #[arg(value_name = "HOSTNAME")]
+        hostname: String,
+    },
+    /// Check the environment for common reasons hostnames don't resolve
+    Doctor {
+        /// Path to the hosts file to check
+        #[arg(value_name = "HOSTS_FILE", default_value = "/etc/hosts")]
+        hosts_file: PathBuf,
+    },
+    /// Query the /readyz endpoint of a running instance (see --metrics-addr); exits with 1 unless it is ready
+    Healthcheck {
+        /// Query /healthz instead, which only checks that the process follows Docker events
+        #[arg(long)]
+        live: bool,
+    },
+    /// Print a hardened systemd unit that syncs the hosts file with the current flags, or write it
+    InstallService {
+        /// Path to the hosts file the service updates
+        #[arg(value_name = "HOSTS_FILE", default_value = "/etc/hosts")]
+        hosts_file: PathBuf,
+
+        /// Write the unit here instead of printing it, e.g. /etc/systemd/system/docker-hostmanager.service
+        #[arg(long, value_name = "PATH")]
+        unit_file: Option<PathBuf>,
     },
     /// Show version information
     Version,
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<()> {
-    let args = Args::parse();
-
-    // Initialize logging
-    let filter = if args.verbose {
-        "docker_hostmanager=debug,bollard=info"
-    } else {
-        "docker_hostmanager=info,bollard=warn"
-    };
+/// Applies the optional, mode-independent settings from `args` to `sync`.
+fn configure(mut sync: Synchronizer, args: &Args) -> Synchronizer {
+    sync = sync.with_network_filter(NetworkFilter::new(
+        args.include_networks.clone(),
+        args.exclude_networks.clone(),
+    ));
+    sync = sync.with_container_filter(ContainerFilter::new(args.filters.clone()));
+    sync = sync.with_rendering(args.group_by, args.annotate);
+    sync = sync.with_lock_timeout(Duration::from_millis(args.lock_timeout_ms));
+    sync = sync.with_output(args.output);
+    if args.gateway_entries {
+        sync = sync.with_gateway_entries(Some(args.host_alias.clone()));
+    }
+    if let Some(instance) = &args.instance {
+        sync = sync.with_instance(instance);
+    }
+    if let Some(notifier) = systemd::Notifier::from_env() {
+        sync = sync.with_notifier(notifier);
+    }
+    sync
+}
 
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_target(false)
-        .with_ansi(true)
-        .init();
+/// Resolves on Ctrl+C, or on SIGTERM/SIGQUIT where there are such signals
+/// (`docker stop`, systemd).
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use signal::unix::{signal as unix_signal, SignalKind};
+        match (
+            unix_signal(SignalKind::terminate()),
+            unix_signal(SignalKind::quit()),
+        ) {
+            (Ok(mut terminate), Ok(mut quit)) => {
+                tokio::select! {
+                    _ = signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                    _ = quit.recv() => {}
+                }
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                warn!("Failed to listen for SIGTERM/SIGQUIT: {}", e);
+                signal::ctrl_c().await.ok();
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    signal::ctrl_c().await.ok();
+}
 
-    println!("{}", "Docker Host Manager".bright_cyan().bold());
-    println!("{}", "===================".bright_cyan());
-    println!("{} {}", "Version:".bright_black(), VERSION.bright_white());
-    println!();
+/// Flushes a pending debounced write, or removes the managed section when
+/// `cleanup` is set, giving up after `timeout_ms`.
+async fn shut_down(sync: &Synchronizer, cleanup: bool, timeout_ms: u64) -> Result<()> {
+    sync.notify("STOPPING=1");
+    let finish = async {
+        if cleanup {
+            sync.cleanup().await
+        } else {
+            sync.flush().await
+        }
+    };
+    tokio::time::timeout(Duration::from_millis(timeout_ms), finish)
+        .await
+        .with_context(|| format!("Shutdown didn't finish within {timeout_ms}ms"))?
+}
 
-    // Connect to Docker
-    println!(
-        "{} {}",
-        "Connecting to Docker at".bright_blue(),
-        args.socket.bright_white()
-    );
-    let docker = Docker::connect_with_socket(&args.socket, 120, bollard::API_DEFAULT_VERSION)
+/// Connects to Docker, reporting progress unless `quiet` is set.
+async fn connect(socket: &str, quiet: bool) -> Result<Docker> {
+    if !quiet {
+        info!(socket, "Connecting to Docker");
+    }
+    let docker = Docker::connect_with_socket(socket, 120, bollard::API_DEFAULT_VERSION)
         .context("Failed to connect to Docker socket")?;
 
     // Verify connection
@@ -107,67 +352,569 @@ async fn main() -> Result<()> {
         .version()
         .await
         .context("Failed to verify Docker connection")?;
+    if !quiet {
+        info!(
+            version = version.version.unwrap_or_default(),
+            "Connected to Docker"
+        );
+    }
+
+    Ok(docker)
+}
+
+/// Performs the initial synchronization, then keeps following Docker events
+/// until shutdown unless `once` is set, serving the HTTP endpoints meanwhile.
+/// Shared by watch and sync.
+async fn run(sync: &Synchronizer, once: bool, cleanup_on_exit: bool, args: &Args) -> Result<()> {
+    // Serving from the start, so health probes get answers during the initial sync
+    let serve = async {
+        match args.metrics_addr {
+            Some(addr) => http::serve(addr, sync.metrics(), sync.health()).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        result = serve => result,
+        result = synchronize_and_listen(sync, once, cleanup_on_exit, args) => result,
+    }
+}
+
+async fn synchronize_and_listen(
+    sync: &Synchronizer,
+    once: bool,
+    cleanup_on_exit: bool,
+    args: &Args,
+) -> Result<()> {
+    // Listening from the start, so a signal during a slow initial sync doesn't
+    // kill the process before it has cleaned up
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    info!("Performing initial synchronization");
+    tokio::select! {
+        biased;
+        () = &mut shutdown => {
+            info!("Received shutdown signal during the initial synchronization, exiting");
+            return shut_down(sync, cleanup_on_exit, args.shutdown_timeout_ms).await;
+        }
+        result = sync.synchronize() => result?,
+    }
+    info!("Initial synchronization complete");
+
+    if once {
+        info!("Running in once mode, exiting");
+        return Ok(());
+    }
+
+    info!("Listening for Docker events (press Ctrl+C to stop)");
+
+    let listened = tokio::select! {
+        result = sync.listen_events() => result,
+        () = &mut shutdown => {
+            info!("Received shutdown signal, exiting gracefully");
+            Ok(())
+        }
+    };
+    // Also when listening failed, so pending writes and --cleanup-on-exit aren't lost
+    let shut = shut_down(sync, cleanup_on_exit, args.shutdown_timeout_ms).await;
+    if let (Err(_), Err(e)) = (&listened, &shut) {
+        warn!("Failed to shut down cleanly: {e:#}");
+    }
+    listened.and(shut)
+}
+
+fn print_status(statuses: &[ContainerStatus]) {
+    if statuses.is_empty() {
+        println!("{} No active containers", "ℹ".bright_blue());
+        return;
+    }
+
+    let (mut owned, mut conflicts, mut rejected) = (0, 0, 0);
+    for status in statuses {
+        println!(
+            "{} {} {}",
+            "→".bright_cyan(),
+            status.name.bright_white().bold(),
+            format!("({})", status.id.get(..12).unwrap_or(&status.id)).bright_black()
+        );
+        for (network, ip) in &status.networks {
+            println!("    {:<9} {network} {ip}", "network".bright_black());
+        }
+        for (ip, hostname) in &status.hostnames {
+            println!(
+                "    {:<9} {ip} {}",
+                "owns".bright_green(),
+                hostname.bright_white()
+            );
+        }
+        for (ip, hostname, reason) in &status.skipped {
+            let label = if matches!(reason, Skip::Conflict { .. }) {
+                conflicts += 1;
+                "conflict".bright_yellow()
+            } else {
+                rejected += 1;
+                "rejected".bright_red()
+            };
+            println!("    {label:<9} {ip} {hostname} ({reason})");
+        }
+        owned += status.hostnames.len();
+    }
+    println!();
     println!(
-        "{} Docker {}",
-        "✓".bright_green(),
-        version.version.unwrap_or_default().bright_white()
+        "{} {} containers, {} hostnames owned, {} conflicts, {} rejected",
+        "ℹ".bright_blue(),
+        statuses.len().to_string().bright_white(),
+        owned.to_string().bright_white(),
+        conflicts.to_string().bright_white(),
+        rejected.to_string().bright_white()
     );
+}
+
+/// Prints the results of `doctor`. Returns `false` if any check failed.
+fn print_checks(checks: &[doctor::Check]) -> bool {
+    for check in checks {
+        let icon = match check.status {
+            doctor::Status::Ok => "✓".bright_green(),
+            doctor::Status::Warn => "!".bright_yellow(),
+            doctor::Status::Fail => "✗".bright_red(),
+        };
+        println!("{icon} {:<11} {}", check.name.bright_white(), check.message);
+        if let Some(hint) = &check.hint {
+            println!("  {:<11} {}", "", hint.bright_black());
+        }
+    }
+
+    let count = |status| checks.iter().filter(|c| c.status == status).count();
+    println!();
+    println!(
+        "{} {} checks, {} warnings, {} failed",
+        "ℹ".bright_blue(),
+        checks.len().to_string().bright_white(),
+        count(doctor::Status::Warn).to_string().bright_white(),
+        count(doctor::Status::Fail).to_string().bright_white()
+    );
+    count(doctor::Status::Fail) == 0
+}
+
+fn print_resolution(hostname: &str, candidates: &[Candidate]) {
+    if candidates.is_empty() {
+        println!(
+            "{} No running container generates {}",
+            "ℹ".bright_blue(),
+            hostname.bright_white()
+        );
+        return;
+    }
+
+    for candidate in candidates {
+        println!(
+            "{} {} {}",
+            "→".bright_cyan(),
+            candidate.name.bright_white().bold(),
+            format!("({})", candidate.id.get(..12).unwrap_or(&candidate.id)).bright_black()
+        );
+        for rule in &candidate.rules {
+            println!("    {:<9} {rule}", "via".bright_black());
+        }
+        let (label, reason) = match &candidate.verdict {
+            Verdict::Owns => ("owns".bright_green(), String::new()),
+            Verdict::Skipped(skip @ Skip::Conflict { .. }) => {
+                ("conflict".bright_yellow(), format!(" ({skip})"))
+            }
+            Verdict::Skipped(skip) => ("rejected".bright_red(), format!(" ({skip})")),
+            Verdict::Filtered(by) => ("filtered".bright_black(), format!(" (by {by})")),
+            Verdict::NotExposed => ("filtered".bright_black(), " (no exposed ports)".to_string()),
+        };
+        println!(
+            "    {label:<9} {} {}{reason}",
+            candidate.ip.as_deref().unwrap_or("(no IP)"),
+            candidate.hostname.bright_white()
+        );
+    }
+
     println!();
+    match candidates.iter().find(|c| c.verdict == Verdict::Owns) {
+        Some(owner) => println!(
+            "{} {} resolves to {} ({})",
+            "✓".bright_green(),
+            hostname.bright_white(),
+            owner.ip.as_deref().unwrap_or_default().bright_white(),
+            owner.name
+        ),
+        None => println!(
+            "{} {} isn't published",
+            "✗".bright_red(),
+            hostname.bright_white()
+        ),
+    }
+}
+
+async fn restore(backups: &Backups, backup: Option<&str>, lock_timeout: Duration) -> Result<()> {
+    let list = backups.list()?;
+    let Some(wanted) = backup else {
+        if list.is_empty() {
+            println!("{} No backups found", "ℹ".bright_blue());
+        } else {
+            println!("{} Backups, newest first:", "→".bright_cyan());
+            for (i, path) in list.iter().enumerate() {
+                println!(
+                    "  {:>3}  {}",
+                    i + 1,
+                    path.display().to_string().bright_white()
+                );
+            }
+        }
+        return Ok(());
+    };
+
+    let path = match wanted.parse::<usize>() {
+        Ok(number) => number
+            .checked_sub(1)
+            .and_then(|i| list.get(i))
+            .cloned()
+            .with_context(|| format!("There is no backup number {number}"))?,
+        Err(_) => PathBuf::from(wanted),
+    };
+    let previous = backups.restore(&path, lock_timeout).await?;
+    println!(
+        "{} Restored from {} (previous contents saved to {})",
+        "✓".bright_green(),
+        path.display(),
+        previous.display()
+    );
+    Ok(())
+}
+
+/// Prints what a sync would change in `hosts_file` as a unified diff, without
+/// writing it. Exits with 1 when there are changes, so it can gate CI jobs.
+async fn preview(hosts_file: &Path, args: &Args) -> Result<ExitCode> {
+    if !hosts_file.exists() {
+        anyhow::bail!("Hosts file does not exist: {}", hosts_file.display());
+    }
+    let docker = connect(&args.socket, true).await?;
+    let sync = configure(
+        Synchronizer::new(
+            docker,
+            hosts_file.to_path_buf(),
+            args.tld.clone(),
+            false, // Dry run, nothing is written
+            args.debounce_ms,
+        ),
+        args,
+    );
+    sync.load().await?;
+    let (current, proposed) = sync.preview().await?;
+
+    let diff = hosts_file::unified_diff(&current, &proposed, hosts_file);
+    if diff.is_empty() {
+        println!(
+            "{} {} is up to date",
+            "✓".bright_green(),
+            hosts_file.display()
+        );
+        return Ok(ExitCode::SUCCESS);
+    }
+    for line in diff.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            println!("{}", line.bold());
+        } else if line.starts_with('+') {
+            println!("{}", line.bright_green());
+        } else if line.starts_with('-') {
+            println!("{}", line.bright_red());
+        } else if line.starts_with("@@") {
+            println!("{}", line.bright_cyan());
+        } else {
+            println!("{line}");
+        }
+    }
+    Ok(ExitCode::FAILURE)
+}
+
+/// Renders a systemd unit running `sync` on `hosts_file` with the global
+/// flags given on the command line or through the environment, then prints
+/// it or writes it to `unit_file`.
+fn install_service(
+    matches: &clap::ArgMatches,
+    args: &Args,
+    hosts_file: &Path,
+    unit_file: Option<&Path>,
+) -> Result<()> {
+    let executable = std::env::current_exe().context("Failed to locate the executable")?;
+    let target = std::path::absolute(hosts_file)
+        .with_context(|| format!("Failed to resolve {}", hosts_file.display()))?;
+
+    let mut exec_start = vec![executable.display().to_string()];
+    exec_start.extend(global_flags(matches));
+    // The rest of the file system is read-only, backups can't go next to the hosts file
+    let mut writable = Vec::new();
+    if args.backup_keep > 0 {
+        match &args.backup_dir {
+            Some(dir) => writable.push(std::path::absolute(dir)?),
+            None => exec_start.push(format!("--backup-dir={}", systemd::STATE_DIRECTORY)),
+        }
+    }
+    exec_start.push("sync".to_string());
+    exec_start.push(target.display().to_string());
+
+    let unit = systemd::unit_file(&exec_start, &target, &writable);
+    let Some(path) = unit_file else {
+        print!("{unit}");
+        return Ok(());
+    };
+    fs::write(path, unit).with_context(|| format!("Failed to write {}", path.display()))?;
+    println!("{} Wrote {}", "✓".bright_green(), path.display());
+    let name = path.file_name().map_or_else(
+        || "docker-hostmanager.service".into(),
+        |n| n.to_string_lossy(),
+    );
+    println!(
+        "{} Enable it with: {}",
+        "ℹ".bright_blue(),
+        format!("systemctl daemon-reload && systemctl enable --now {name}").bright_white()
+    );
+    Ok(())
+}
+
+/// The global flags set on the command line or through the environment, as
+/// `--name=value` arguments. Defaults are left out.
+fn global_flags(matches: &clap::ArgMatches) -> Vec<String> {
+    let command = Args::command();
+    let mut flags = Vec::new();
+    for arg in command.get_arguments().filter(|a| a.is_global_set()) {
+        let (id, Some(long)) = (arg.get_id().as_str(), arg.get_long()) else {
+            continue;
+        };
+        if !matches!(
+            matches.value_source(id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+        if matches!(arg.get_action(), ArgAction::SetTrue) {
+            if matches.get_flag(id) {
+                flags.push(format!("--{long}"));
+            }
+            continue;
+        }
+        for value in matches.get_raw(id).into_iter().flatten() {
+            flags.push(format!("--{long}={}", value.to_string_lossy()));
+        }
+    }
+    flags
+}
+
+fn list_sections(hosts_file: &Path) -> Result<()> {
+    let content = fs::read_to_string(hosts_file)
+        .with_context(|| format!("Failed to read {}", hosts_file.display()))?;
+    let sections = hosts_file::sections(&content);
+    if sections.is_empty() {
+        println!(
+            "{} No managed sections in {}",
+            "ℹ".bright_blue(),
+            hosts_file.display()
+        );
+        return Ok(());
+    }
+
+    println!(
+        "{} Managed sections in {}:",
+        "→".bright_cyan(),
+        hosts_file.display()
+    );
+    for section in sections {
+        let owner = section.instance.as_deref().unwrap_or("(default)");
+        let location = match (section.start_line, section.end_line) {
+            (Some(start), Some(end)) => format!("lines {start}-{end}"),
+            (Some(start), None) => format!("line {start}, missing end marker"),
+            (None, Some(end)) => format!("line {end}, end marker without a start"),
+            (None, None) => String::new(),
+        };
+        let noun = if section.entries == 1 {
+            "entry"
+        } else {
+            "entries"
+        };
+        println!(
+            "  {:<20} {:<36} {} {noun}",
+            owner.bright_white(),
+            location,
+            section.entries
+        );
+    }
+    Ok(())
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<ExitCode> {
+    // Keep the matches, install-service needs to know which flags were given
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    // Keep stdout for the NDJSON stream in JSON mode
+    logging::init(
+        args.log_format,
+        args.verbose,
+        args.output == OutputFormat::Json,
+    );
+    colored::control::set_override(logging::use_color(&std::io::stdout()));
+
+    // The unit file is printed on its own, so it can be redirected
+    let printing_unit = matches!(
+        args.command,
+        Some(Commands::InstallService {
+            unit_file: None,
+            ..
+        })
+    );
+    if !printing_unit {
+        info!(version = VERSION, "Docker Host Manager");
+    }
 
     // Determine command (default to watch)
-    let command = args.command.unwrap_or(Commands::Watch { once: false });
+    let command = args
+        .command
+        .take()
+        .unwrap_or(Commands::Watch { once: false });
 
     match command {
         Commands::Version => {
             println!("dkarlovi/{PACKAGE_NAME} {VERSION}");
-            return Ok(());
+            return Ok(ExitCode::SUCCESS);
         }
-        Commands::Watch { once } => {
-            println!(
-                "{} Watch mode - displaying hostname changes only",
-                "ℹ".bright_blue()
-            );
-            println!();
-
-            let sync = Synchronizer::new(
-                docker,
-                PathBuf::from("/etc/hosts"), // Unused in watch mode
-                args.tld.clone(),
-                false, // Never write in watch mode
-                args.debounce_ms,
-            );
-
-            println!(
-                "{}",
-                "Performing initial synchronization...".bright_yellow()
+        Commands::Clean { hosts_file } => {
+            let markers = args
+                .instance
+                .as_deref()
+                .map_or_else(Markers::default, Markers::for_instance);
+            let backups = (args.backup_keep > 0)
+                .then(|| Backups::new(&hosts_file, args.backup_dir.clone(), args.backup_keep));
+            if hosts_file::remove_section(
+                &hosts_file,
+                &markers,
+                backups.as_ref(),
+                Duration::from_millis(args.lock_timeout_ms),
+            )
+            .await?
+            {
+                println!(
+                    "{} Removed managed section from {}",
+                    "✓".bright_green(),
+                    hosts_file.display()
+                );
+            } else {
+                println!(
+                    "{} No managed section in {}",
+                    "ℹ".bright_blue(),
+                    hosts_file.display()
+                );
+            }
+        }
+        Commands::Restore { hosts_file, backup } => {
+            // Restoring always keeps a backup of what it replaces
+            let backups = Backups::new(
+                &hosts_file,
+                args.backup_dir.clone(),
+                args.backup_keep.max(1),
             );
-            sync.synchronize().await?;
-            println!(
-                "{} {}",
-                "✓".bright_green(),
-                "Initial synchronization complete".bright_white()
+            restore(
+                &backups,
+                backup.as_deref(),
+                Duration::from_millis(args.lock_timeout_ms),
+            )
+            .await?;
+        }
+        Commands::ListSections { hosts_file } => {
+            list_sections(&hosts_file)?;
+        }
+        Commands::Doctor { hosts_file } => {
+            let checks = doctor::run(&args.socket, &hosts_file, args.instance.as_deref()).await;
+            if !print_checks(&checks) {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Commands::InstallService {
+            hosts_file,
+            unit_file,
+        } => {
+            install_service(&matches, &args, &hosts_file, unit_file.as_deref())?;
+        }
+        Commands::Status => {
+            let docker = connect(&args.socket, false).await?;
+            let sync = configure(
+                Synchronizer::new(
+                    docker,
+                    PathBuf::from("/etc/hosts"), // Unused, nothing is written
+                    args.tld.clone(),
+                    false,
+                    args.debounce_ms,
+                ),
+                &args,
             );
-
-            if once {
-                println!("{}", "Running in once mode, exiting...".bright_yellow());
-                return Ok(());
+            sync.load().await?;
+            print_status(&sync.status().await);
+        }
+        Commands::Healthcheck { live } => {
+            let mut addr = args.metrics_addr.context(
+                "healthcheck needs --metrics-addr (METRICS_ADDR) of the instance to check",
+            )?;
+            // The server listens on all interfaces; ask it over loopback
+            if addr.ip().is_unspecified() {
+                addr.set_ip(if addr.is_ipv4() {
+                    Ipv4Addr::LOCALHOST.into()
+                } else {
+                    Ipv6Addr::LOCALHOST.into()
+                });
             }
-
-            println!();
-            println!("{}", "Listening for Docker events...".bright_yellow());
-            println!("{}", "(Press Ctrl+C to stop)".bright_black());
-
-            tokio::select! {
-                result = sync.listen_events() => {
-                    result?;
-                }
-                _ = signal::ctrl_c() => {
-                    println!();
-                    println!("{}", "Received shutdown signal, exiting gracefully...".bright_yellow());
-                }
+            let path = if live { "/healthz" } else { "/readyz" };
+            let (status, body) = http::get(addr, path).await?;
+            println!("{path}: {status} {}", body.trim());
+            if status != 200 {
+                return Ok(ExitCode::FAILURE);
             }
         }
-        Commands::Sync { hosts_file, once } => {
+        Commands::Resolve { hostname } => {
+            let docker = connect(&args.socket, false).await?;
+            let sync = configure(
+                Synchronizer::new(
+                    docker,
+                    PathBuf::from("/etc/hosts"), // Unused, nothing is written
+                    args.tld.clone(),
+                    false,
+                    args.debounce_ms,
+                ),
+                &args,
+            );
+            print_resolution(&hostname, &sync.resolve(&hostname).await?);
+        }
+        Commands::Watch { once } => {
+            let docker = connect(&args.socket, false).await?;
+            info!("Watch mode - displaying hostname changes only");
+
+            let sync = configure(
+                Synchronizer::new(
+                    docker,
+                    PathBuf::from("/etc/hosts"), // Unused in watch mode
+                    args.tld.clone(),
+                    false, // Never write in watch mode
+                    args.debounce_ms,
+                ),
+                &args,
+            );
+            run(&sync, once, false, &args).await?;
+        }
+        Commands::Sync {
+            hosts_file,
+            once,
+            cleanup_on_exit,
+            dry_run,
+        } => {
+            if dry_run {
+                // 1 means changes, like diff; errors need a code of their own
+                return Ok(preview(&hosts_file, &args).await.unwrap_or_else(|e| {
+                    eprintln!("{} {:#}", "✗".bright_red(), e);
+                    ExitCode::from(2)
+                }));
+            }
             if !hosts_file.exists() {
                 eprintln!(
                     "{} Hosts file does not exist: {}",
@@ -177,52 +924,28 @@ async fn main() -> Result<()> {
                 return Err(anyhow::anyhow!("Hosts file does not exist"));
             }
 
-            println!(
-                "{} Sync mode - will update {}",
-                "✓".bright_green(),
-                hosts_file.display()
-            );
-            println!();
-
-            let sync = Synchronizer::new(
-                docker,
-                hosts_file.clone(),
-                args.tld.clone(),
-                true, // Always write in sync mode
-                args.debounce_ms,
-            );
+            let docker = connect(&args.socket, false).await?;
+            info!(path = %hosts_file.display(), "Sync mode - will update the hosts file");
 
-            println!(
-                "{}",
-                "Performing initial synchronization...".bright_yellow()
-            );
-            sync.synchronize().await?;
-            println!(
-                "{} {}",
-                "✓".bright_green(),
-                "Initial synchronization complete".bright_white()
+            let mut sync = configure(
+                Synchronizer::new(
+                    docker,
+                    hosts_file.clone(),
+                    args.tld.clone(),
+                    true, // Always write in sync mode
+                    args.debounce_ms,
+                ),
+                &args,
             );
-
-            if once {
-                println!("{}", "Running in once mode, exiting...".bright_yellow());
-                return Ok(());
-            }
-
-            println!();
-            println!("{}", "Listening for Docker events...".bright_yellow());
-            println!("{}", "(Press Ctrl+C to stop)".bright_black());
-
-            tokio::select! {
-                result = sync.listen_events() => {
-                    result?;
-                }
-                _ = signal::ctrl_c() => {
-                    println!();
-                    println!("{}", "Received shutdown signal, exiting gracefully...".bright_yellow());
-                }
+            if args.backup_keep > 0 {
+                sync = sync.with_backups(
+                    Backups::new(&hosts_file, args.backup_dir.clone(), args.backup_keep),
+                    args.backup_when,
+                );
             }
+            run(&sync, once, cleanup_on_exit, &args).await?;
         }
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
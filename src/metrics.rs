@@ -0,0 +1,261 @@
+//! Counters and gauges of the daemon, rendered in the Prometheus text format.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Upper bounds of the write latency histogram buckets, in seconds.
+const WRITE_DURATION_BUCKETS: [f64; 8] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Docker events received, by `(type, action)`.
+    events: Mutex<BTreeMap<(String, String), u64>>,
+    pub active_containers: AtomicU64,
+    pub hostnames_published: AtomicU64,
+    /// Hostnames left out for an unresolved variable or an unexpanded wildcard.
+    pub hostnames_rejected: AtomicU64,
+    pub conflicts: AtomicU64,
+    pub writes: AtomicU64,
+    /// Writes skipped because the file was already up to date.
+    pub skipped_writes: AtomicU64,
+    /// Writes requested by events, before debouncing.
+    pub write_requests: AtomicU64,
+    /// Writes performed once the debounce delay expired.
+    pub debounced_writes: AtomicU64,
+    pub reconnects: AtomicU64,
+    last_write: AtomicU64,
+    write_duration: Histogram,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Cumulative counts, one per bucket of `WRITE_DURATION_BUCKETS`.
+    buckets: [AtomicU64; WRITE_DURATION_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Metrics {
+    pub fn record_event(&self, event_type: &str, action: &str) {
+        if let Ok(mut events) = self.events.lock() {
+            *events
+                .entry((event_type.to_string(), action.to_string()))
+                .or_default() += 1;
+        }
+    }
+
+    /// Counts a hosts file write that took `duration`, and remembers when it
+    /// happened.
+    pub fn record_write(&self, duration: Duration) {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        let seconds = duration.as_secs_f64();
+        for (bucket, bound) in self
+            .write_duration
+            .buckets
+            .iter()
+            .zip(WRITE_DURATION_BUCKETS)
+        {
+            if seconds <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.write_duration.count.fetch_add(1, Ordering::Relaxed);
+        self.write_duration.sum_micros.fetch_add(
+            u64::try_from(duration.as_micros()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.last_write.store(now.as_secs(), Ordering::Relaxed);
+    }
+}
+
+/// Renders every metric in the Prometheus text exposition format.
+impl fmt::Display for Metrics {
+    #[allow(clippy::cast_precision_loss)] // Counters stay far below 2^52
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let load = |value: &AtomicU64| value.load(Ordering::Relaxed);
+
+        header(f, "events_total", "counter", "Docker events received")?;
+        if let Ok(events) = self.events.lock() {
+            for ((event_type, action), count) in events.iter() {
+                writeln!(
+                    f,
+                    "docker_hostmanager_events_total{{type=\"{}\",action=\"{}\"}} {count}",
+                    escape(event_type),
+                    escape(action)
+                )?;
+            }
+        }
+
+        let simple = [
+            (
+                "active_containers",
+                "gauge",
+                "Containers with published hostnames",
+                load(&self.active_containers),
+            ),
+            (
+                "hostnames_published",
+                "gauge",
+                "Hostnames in the managed section",
+                load(&self.hostnames_published),
+            ),
+            (
+                "hostnames_rejected",
+                "gauge",
+                "Hostnames left out for an unresolved variable or a wildcard without subdomains",
+                load(&self.hostnames_rejected),
+            ),
+            (
+                "hostname_conflicts_total",
+                "counter",
+                "Hostnames not claimed because another container owns them",
+                load(&self.conflicts),
+            ),
+            (
+                "writes_total",
+                "counter",
+                "Writes of the hosts file",
+                load(&self.writes),
+            ),
+            (
+                "skipped_writes_total",
+                "counter",
+                "Writes skipped because the hosts file was up to date",
+                load(&self.skipped_writes),
+            ),
+            (
+                "write_requests_total",
+                "counter",
+                "Writes requested by events, before debouncing",
+                load(&self.write_requests),
+            ),
+            (
+                "debounced_writes_total",
+                "counter",
+                "Writes performed after the debounce delay",
+                load(&self.debounced_writes),
+            ),
+            (
+                "event_stream_reconnects_total",
+                "counter",
+                "Reconnects to the Docker event stream",
+                load(&self.reconnects),
+            ),
+            (
+                "last_write_timestamp_seconds",
+                "gauge",
+                "Unix time of the last successful write",
+                load(&self.last_write),
+            ),
+        ];
+        for (name, kind, help, value) in simple {
+            header(f, name, kind, help)?;
+            writeln!(f, "docker_hostmanager_{name} {value}")?;
+        }
+
+        let debounced = load(&self.debounced_writes);
+        header(
+            f,
+            "debounce_coalescing_ratio",
+            "gauge",
+            "Write requests per debounced write",
+        )?;
+        let ratio = if debounced == 0 {
+            0.0
+        } else {
+            load(&self.write_requests) as f64 / debounced as f64
+        };
+        writeln!(f, "docker_hostmanager_debounce_coalescing_ratio {ratio}")?;
+
+        header(
+            f,
+            "write_duration_seconds",
+            "histogram",
+            "Time taken by hosts file writes, including waiting for the lock",
+        )?;
+        let histogram = &self.write_duration;
+        for (bucket, bound) in histogram.buckets.iter().zip(WRITE_DURATION_BUCKETS) {
+            writeln!(
+                f,
+                "docker_hostmanager_write_duration_seconds_bucket{{le=\"{bound}\"}} {}",
+                load(bucket)
+            )?;
+        }
+        let count = load(&histogram.count);
+        writeln!(
+            f,
+            "docker_hostmanager_write_duration_seconds_bucket{{le=\"+Inf\"}} {count}"
+        )?;
+        writeln!(
+            f,
+            "docker_hostmanager_write_duration_seconds_sum {}",
+            load(&histogram.sum_micros) as f64 / 1_000_000.0
+        )?;
+        writeln!(f, "docker_hostmanager_write_duration_seconds_count {count}")
+    }
+}
+
+fn header(f: &mut fmt::Formatter<'_>, name: &str, kind: &str, help: &str) -> fmt::Result {
+    writeln!(f, "# HELP docker_hostmanager_{name} {help}")?;
+    writeln!(f, "# TYPE docker_hostmanager_{name} {kind}")
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::indexing_slicing
+)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.record_event("container", "start");
+        metrics.record_event("container", "start");
+        metrics.record_event("network", "connect");
+        metrics.write_requests.store(6, Ordering::Relaxed);
+        metrics.debounced_writes.store(2, Ordering::Relaxed);
+        metrics.record_write(Duration::from_millis(20));
+
+        let text = metrics.to_string();
+        for line in [
+            "docker_hostmanager_events_total{type=\"container\",action=\"start\"} 2",
+            "docker_hostmanager_events_total{type=\"network\",action=\"connect\"} 1",
+            "docker_hostmanager_writes_total 1",
+            "docker_hostmanager_debounce_coalescing_ratio 3",
+            "docker_hostmanager_write_duration_seconds_bucket{le=\"0.01\"} 0",
+            "docker_hostmanager_write_duration_seconds_bucket{le=\"0.05\"} 1",
+            "docker_hostmanager_write_duration_seconds_bucket{le=\"+Inf\"} 1",
+            "docker_hostmanager_write_duration_seconds_sum 0.02",
+            "# TYPE docker_hostmanager_write_duration_seconds histogram",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {line} in\n{text}");
+        }
+        assert!(
+            !text.contains("docker_hostmanager_last_write_timestamp_seconds 0\n"),
+            "the write time is recorded"
+        );
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -0,0 +1,124 @@
+//! Machine-readable output: one JSON object per line (NDJSON) on stdout, for
+//! each container event and each rendered state of the managed section.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tracing::warn;
+
+/// How watch and sync report what they do.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Colored, human-oriented text
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    ContainerUp {
+        id: &'a str,
+        name: &'a str,
+        ip_address: Option<&'a str>,
+        /// Network name to IP address.
+        networks: BTreeMap<&'a str, &'a str>,
+    },
+    ContainerDown {
+        id: &'a str,
+        name: &'a str,
+    },
+    /// `container` generates `hostname`, but `owner` claimed it first.
+    Conflict {
+        hostname: &'a str,
+        container: Container<'a>,
+        owner: Container<'a>,
+    },
+    /// The full managed section after a change.
+    Entries {
+        /// Whether the hosts file was updated; always `false` in watch mode.
+        written: bool,
+        containers: usize,
+        hostnames: usize,
+        entries: Vec<Entry<'a>>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct Container<'a> {
+    pub id: &'a str,
+    pub name: &'a str,
+}
+
+/// One line of the managed section.
+#[derive(Debug, Serialize)]
+pub struct Entry<'a> {
+    pub ip: &'a str,
+    pub hostnames: &'a [String],
+    /// Hostnames left out, e.g. because another container owns them.
+    pub skipped: &'a [String],
+    /// `None` for gateway entries.
+    pub owner: Option<Container<'a>>,
+    pub project: Option<&'a str>,
+    pub network: Option<&'a str>,
+}
+
+/// Prints `event` as a single JSON line.
+pub fn emit(event: &Event<'_>) {
+    match serde_json::to_string(event) {
+        Ok(line) => println!("{line}"),
+        Err(e) => warn!("Failed to serialize event: {}", e),
+    }
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::indexing_slicing
+)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_are_tagged_single_line_json() {
+        let line = serde_json::to_string(&Event::Conflict {
+            hostname: "shop.local",
+            container: Container {
+                id: "bbb",
+                name: "web-2",
+            },
+            owner: Container {
+                id: "aaa",
+                name: "web-1",
+            },
+        })
+        .unwrap();
+        assert_eq!(
+            line,
+            r#"{"event":"conflict","hostname":"shop.local","container":{"id":"bbb","name":"web-2"},"owner":{"id":"aaa","name":"web-1"}}"#
+        );
+
+        let hostnames = vec!["web.docker".to_string()];
+        let line = serde_json::to_string(&Event::Entries {
+            written: true,
+            containers: 1,
+            hostnames: 1,
+            entries: vec![Entry {
+                ip: "172.17.0.2",
+                hostnames: &hostnames,
+                skipped: &[],
+                owner: None,
+                project: None,
+                network: None,
+            }],
+        })
+        .unwrap();
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["event"], "entries");
+        assert_eq!(value["entries"][0]["hostnames"][0], "web.docker");
+        assert!(value["entries"][0]["owner"].is_null());
+    }
+}
@@ -1,21 +1,278 @@
 use anyhow::{Context, Result};
-use bollard::models::{ContainerInspectResponse, EventActor, EventMessage, EventMessageTypeEnum};
+use bollard::models::{
+    ContainerConfig, ContainerInspectResponse, EventActor, EventMessage, EventMessageTypeEnum,
+};
 use bollard::query_parameters::{EventsOptions, InspectContainerOptions, ListContainersOptions};
 use bollard::Docker;
-use colored::Colorize;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
+use std::net::IpAddr;
 use std::path::PathBuf;
+use std::sync::atomic::{self, AtomicBool};
 use std::sync::Arc;
 use tokio::sync::{Mutex, Notify};
-use tokio::time::{sleep, Duration};
+use tokio::time::{sleep, Duration, Instant};
 use tokio_stream::StreamExt;
 use tracing::{debug, error, info, warn};
 
-use crate::types::{ContainerInfo, NetworkInfo};
+use crate::backup::{BackupWhen, Backups};
+use crate::filter::{glob_match, ContainerFilter, NetworkFilter};
+use crate::health::Health;
+use crate::hosts_file::{self, LockedFile, Markers};
+use crate::metrics::Metrics;
+use crate::output::{self, Event, OutputFormat};
+use crate::systemd::Notifier;
+use crate::template::{self, TemplateError};
+use crate::types::{network_matches, ContainerInfo, NetworkInfo};
+
+/// Delays before reconnecting to the Docker event stream; doubled after each
+/// failed attempt, up to the maximum.
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+/// Delay before retrying a failed write, doubled on each failure up to the max.
+const WRITE_RETRY_MIN_DELAY: Duration = Duration::from_secs(1);
+const WRITE_RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// How long a write waits for the hosts file lock by default.
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Native container labels. These take precedence over the `DOMAIN_NAME` env var.
+/// Comma-separated domain names (templates allowed), replacing `DOMAIN_NAME`.
+const DOMAINS_LABEL: &str = "docker-hostmanager.domains";
+/// Comma-separated extra aliases, published as `<alias>.<network>`.
+const ALIASES_LABEL: &str = "docker-hostmanager.aliases";
+/// Comma-separated networks (or globs) to publish on; the others get no entries.
+const NETWORKS_LABEL: &str = "docker-hostmanager.networks";
+/// Comma-separated generated hostnames that should not be published.
+const EXCLUDE_NAMES_LABEL: &str = "docker-hostmanager.exclude-names";
+/// Per-container override of the `--tld` option.
+const TLD_LABEL: &str = "docker-hostmanager.tld";
+/// Comma-separated subdomains that wildcard domains are expanded to.
+const SUBDOMAINS_LABEL: &str = "docker-hostmanager.subdomains";
+/// Domains label used by orbstack, added to the other domain names.
+const ORBSTACK_DOMAINS_LABEL: &str = "dev.orbstack.domains";
+
+/// How entries in the managed section are grouped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum GroupBy {
+    /// A flat list sorted by IP.
+    #[default]
+    None,
+    /// Under a `# project: <name>` header per compose project.
+    Project,
+    /// Under a `# network: <name>` header per Docker network.
+    Network,
+}
+
+/// Why a generated hostname isn't published.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Skip {
+    /// Another container claimed it first.
+    Conflict {
+        owner_id: String,
+        owner_name: String,
+    },
+    /// A template variable in it couldn't be resolved, e.g. `{MISSING_VAR}`.
+    UnresolvedVariable,
+    /// A wildcard domain with no subdomains to expand to.
+    UnexpandedWildcard,
+}
+
+impl Skip {
+    /// Returns why `hostname` isn't published for `container_id`, or `None`
+    /// if the container owns it.
+    fn check(
+        hostname: &str,
+        container_id: &str,
+        claims: &HashMap<String, (String, String)>,
+    ) -> Option<Self> {
+        if hostname.contains('{') && hostname.contains('}') {
+            return Some(Self::UnresolvedVariable);
+        }
+        if hostname.contains('*') {
+            return Some(Self::UnexpandedWildcard);
+        }
+        claims
+            .get(hostname)
+            .filter(|(owner_id, _)| owner_id != container_id)
+            .map(|(owner_id, owner_name)| Self::Conflict {
+                owner_id: owner_id.clone(),
+                owner_name: owner_name.clone(),
+            })
+    }
+}
+
+impl std::fmt::Display for Skip {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Conflict { owner_name, .. } => write!(f, "claimed by \"{owner_name}\""),
+            Self::UnresolvedVariable => write!(f, "unresolved variable"),
+            Self::UnexpandedWildcard => write!(f, "wildcard without subdomains"),
+        }
+    }
+}
+
+/// What the synchronizer currently knows about one active container.
+#[derive(Debug, Clone)]
+pub struct ContainerStatus {
+    pub id: String,
+    pub name: String,
+    /// `(network, ip)`, sorted by network.
+    pub networks: Vec<(String, String)>,
+    /// `(ip, hostname)` pairs this container owns.
+    pub hostnames: Vec<(String, String)>,
+    /// `(ip, hostname, reason)` for generated hostnames that aren't published.
+    pub skipped: Vec<(String, String, Skip)>,
+}
+
+/// The rule through which a container generates a hostname.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    /// `<name>.<network>`, or `<name><tld>` for a container without networks
+    /// or with the TLD label.
+    ContainerName,
+    /// `<alias>.<network>`, from a network alias or the aliases label.
+    Alias(String),
+    /// An entry of a domain list: the domains label, `DOMAIN_NAME` or the
+    /// orbstack label, with the raw value it was rendered from.
+    Domain { source: &'static str, raw: String },
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ContainerName => write!(f, "container name"),
+            Self::Alias(alias) => write!(f, "alias \"{alias}\""),
+            Self::Domain { source, raw } if raw.contains('{') => {
+                write!(f, "{source} template \"{raw}\"")
+            }
+            Self::Domain { source, raw } => write!(f, "{source} \"{raw}\""),
+        }
+    }
+}
+
+/// Whether a container that generates a hostname gets it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Owns,
+    Skipped(Skip),
+    /// Left out by a filter or the exclude-names label, which one is given.
+    Filtered(&'static str),
+    /// Not running, or without an IP address to publish.
+    NotExposed,
+}
+
+/// A container generating a hostname that `resolve` was asked about.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub id: String,
+    pub name: String,
+    /// `None` for a container without an IP, see [`Verdict::NotExposed`].
+    pub ip: Option<String>,
+    /// The hostname as generated, which differs from the one asked about when
+    /// it contains an unresolved variable or an unexpanded wildcard.
+    pub hostname: String,
+    pub rules: Vec<Rule>,
+    pub verdict: Verdict,
+}
+
+/// Turns each `{...}` placeholder left in a generated hostname into a `*`, so
+/// it can be matched against the hostname the user expected.
+fn template_placeholders_as_globs(hostname: &str) -> String {
+    let mut pattern = String::with_capacity(hostname.len());
+    let mut rest = hostname;
+    while let Some((before, after)) = rest.split_once('{') {
+        pattern.push_str(before);
+        if let Some((_, tail)) = after.split_once('}') {
+            pattern.push('*');
+            rest = tail;
+        } else {
+            pattern.push('{');
+            rest = after;
+        }
+    }
+    pattern.push_str(rest);
+    pattern
+}
+
+/// One line of the managed section, with enough context to group and annotate it.
+struct HostEntry {
+    ip: String,
+    /// Hostnames written for `ip`; empty when every one of them was skipped.
+    hostnames: Vec<String>,
+    skipped: Vec<String>,
+    /// `(container name, container id)`; `None` for gateway entries.
+    owner: Option<(String, String)>,
+    project: Option<String>,
+    network: Option<String>,
+}
+
+impl HostEntry {
+    fn render(&self, annotate: bool) -> String {
+        if self.hostnames.is_empty() {
+            // All hostnames for this IP were claimed by another container.
+            // Write a comment-only line so the skip is visible in the file.
+            return format!(
+                "# {} ({}): all hostnames skipped: {}",
+                self.ip,
+                self.owner_name(),
+                self.skipped.join(", ")
+            );
+        }
+
+        let mut comments = Vec::new();
+        if let Some((name, id)) = self.owner.as_ref().filter(|_| annotate) {
+            comments.push(format!("{name} ({})", id.get(..12).unwrap_or(id)));
+        }
+        if !self.skipped.is_empty() {
+            comments.push(format!("skipped: {}", self.skipped.join(", ")));
+        }
+        let comment = if comments.is_empty() {
+            String::new()
+        } else {
+            format!("  # {}", comments.join("; "))
+        };
+        format!("{} {}{}", self.ip, self.hostnames.join(" "), comment)
+    }
+
+    /// The hostname entries are ordered by within a group.
+    fn sort_hostname(&self) -> &str {
+        self.hostnames
+            .first()
+            .or_else(|| self.skipped.first())
+            .map_or("", String::as_str)
+    }
+
+    fn owner_name(&self) -> &str {
+        self.owner.as_ref().map_or("", |(name, _)| name.as_str())
+    }
+
+    /// Total order for the flat layout: numeric IP, then container name, then
+    /// hostname, so the output doesn't depend on `HashMap` iteration order.
+    fn cmp_by_ip(a: &Self, b: &Self) -> Ordering {
+        compare_ips(&a.ip, &b.ip)
+            .then_with(|| a.owner_name().cmp(b.owner_name()))
+            .then_with(|| a.sort_hostname().cmp(b.sort_hostname()))
+    }
 
-const START_TAG: &str = "## docker-hostmanager-start";
-const END_TAG: &str = "## docker-hostmanager-end";
+    /// Total order within a group: hostname, then numeric IP, then container name.
+    fn cmp_by_hostname(a: &Self, b: &Self) -> Ordering {
+        a.sort_hostname()
+            .cmp(b.sort_hostname())
+            .then_with(|| compare_ips(&a.ip, &b.ip))
+            .then_with(|| a.owner_name().cmp(b.owner_name()))
+    }
+}
+
+/// Compares IP addresses numerically (IPv4 before IPv6), falling back to a
+/// string comparison for anything that doesn't parse.
+fn compare_ips(a: &str, b: &str) -> Ordering {
+    match (a.parse::<IpAddr>(), b.parse::<IpAddr>()) {
+        (Ok(a_ip), Ok(b_ip)) => a_ip.cmp(&b_ip),
+        _ => a.cmp(b),
+    }
+}
 
 pub struct Synchronizer {
     docker: Docker,
@@ -28,7 +285,35 @@ pub struct Synchronizer {
     /// Dynamic network-alias hostnames are also tracked here; they just never conflict
     /// because they always include a unique network name.
     hostname_claims: Arc<Mutex<HashMap<String, (String, String)>>>,
+    /// Hostnames in `hostname_claims` that were claimed through a wildcard
+    /// expansion; an exact claim for one of these takes it over.
+    wildcard_claims: Arc<Mutex<HashSet<String>>>,
     write_notify: Notify,
+    /// When set, every network gateway gets a `gateway.<network>` entry.
+    gateway_entries: bool,
+    /// Extra hostname (e.g. `host.docker.internal`) published on the first gateway.
+    host_alias: Option<String>,
+    group_by: GroupBy,
+    /// Append a `# container-name (short id)` comment to every entry.
+    annotate: bool,
+    network_filter: NetworkFilter,
+    container_filter: ContainerFilter,
+    metrics: Arc<Metrics>,
+    health: Arc<Health>,
+    notifier: Option<Notifier>,
+    /// Waiting for Docker to reconnect to the event stream, which the
+    /// watchdog doesn't count as a hang.
+    reconnecting: AtomicBool,
+    /// A write was scheduled but hasn't happened yet.
+    write_pending: AtomicBool,
+    /// Checksum of the managed section as last written, to detect external edits.
+    last_section: Mutex<Option<u64>>,
+    markers: Markers,
+    backups: Option<(Backups, BackupWhen)>,
+    lock_timeout: Duration,
+    output: OutputFormat,
+    /// A backup was taken during this session.
+    backed_up: AtomicBool,
 }
 
 impl Synchronizer {
@@ -47,17 +332,136 @@ impl Synchronizer {
             debounce_ms,
             active_containers: Arc::new(Mutex::new(HashMap::new())),
             hostname_claims: Arc::new(Mutex::new(HashMap::new())),
+            wildcard_claims: Arc::new(Mutex::new(HashSet::new())),
             write_notify: Notify::new(),
+            gateway_entries: false,
+            host_alias: None,
+            group_by: GroupBy::None,
+            annotate: false,
+            network_filter: NetworkFilter::default(),
+            container_filter: ContainerFilter::default(),
+            metrics: Arc::new(Metrics::default()),
+            health: Arc::new(Health::default()),
+            notifier: None,
+            reconnecting: AtomicBool::new(false),
+            write_pending: AtomicBool::new(false),
+            last_section: Mutex::new(None),
+            markers: Markers::default(),
+            backups: None,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            output: OutputFormat::Text,
+            backed_up: AtomicBool::new(false),
+        }
+    }
+
+    /// Sets how long to wait for other programs to release their lock on the
+    /// hosts file before giving up on a write.
+    #[must_use]
+    pub const fn with_lock_timeout(mut self, lock_timeout: Duration) -> Self {
+        self.lock_timeout = lock_timeout;
+        self
+    }
+
+    /// Counters and gauges of this synchronizer, for the metrics endpoint.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// Liveness and readiness of this synchronizer, for the health endpoints.
+    pub fn health(&self) -> Arc<Health> {
+        Arc::clone(&self.health)
+    }
+
+    /// Reports readiness, status and watchdog pings to systemd.
+    #[must_use]
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    /// Sends `state` to systemd, if started by it.
+    pub fn notify(&self, state: &str) {
+        if let Some(notifier) = &self.notifier {
+            notifier.notify(state);
         }
     }
 
+    /// Reports events and rendered states as NDJSON instead of text.
+    #[must_use]
+    pub const fn with_output(mut self, output: OutputFormat) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Backs up the hosts file before it is modified.
+    #[must_use]
+    pub fn with_backups(mut self, backups: Backups, when: BackupWhen) -> Self {
+        self.backups = Some((backups, when));
+        self
+    }
+
+    /// Manages the `## docker-hostmanager-start:<instance>` section instead of
+    /// the default one, so several instances can share a hosts file.
+    #[must_use]
+    pub fn with_instance(mut self, instance: &str) -> Self {
+        self.markers = Markers::for_instance(instance);
+        self
+    }
+
+    /// Scopes this instance to containers matching Docker-style filters. Other
+    /// containers are never inspected into `active_containers`, so their events
+    /// can't affect claims.
+    #[must_use]
+    pub fn with_container_filter(mut self, container_filter: ContainerFilter) -> Self {
+        self.container_filter = container_filter;
+        self
+    }
+
+    /// Sets how the managed section is laid out.
+    #[must_use]
+    pub const fn with_rendering(mut self, group_by: GroupBy, annotate: bool) -> Self {
+        self.group_by = group_by;
+        self.annotate = annotate;
+        self
+    }
+
+    /// Restricts the networks containers are published on. Applied when a
+    /// container is inspected, so excluded networks never claim hostnames.
+    #[must_use]
+    pub fn with_network_filter(mut self, network_filter: NetworkFilter) -> Self {
+        self.network_filter = network_filter;
+        self
+    }
+
+    /// Enables `gateway.<network>` entries for every network an active container
+    /// is attached to. `host_alias`, when given, is added to the gateway of the
+    /// first network (by name) so host-side tooling and containers agree on how
+    /// to reach the host.
+    #[must_use]
+    pub fn with_gateway_entries(mut self, host_alias: Option<String>) -> Self {
+        self.gateway_entries = true;
+        self.host_alias = host_alias.filter(|a| !a.is_empty());
+        self
+    }
+
     pub async fn synchronize(&self) -> Result<()> {
+        self.load().await?;
+        self.write_hosts_file_immediate().await?;
+        self.health.set_synchronized();
+        self.notify("READY=1");
+        Ok(())
+    }
+
+    /// Rebuilds `active_containers` and the hostname claims from the running
+    /// containers, without writing anything.
+    pub async fn load(&self) -> Result<()> {
         info!("Fetching running containers...");
 
         let containers = self
             .docker
             .list_containers(Some(ListContainersOptions {
                 all: false,
+                filters: Some(self.container_filter.list_filters()),
                 ..Default::default()
             }))
             .await
@@ -65,15 +469,16 @@ impl Synchronizer {
 
         info!("Found {} running containers", containers.len());
 
-        // Clear all state for a clean rebuild
-        {
-            let mut active = self.active_containers.lock().await;
-            active.clear();
-        }
-        {
-            let mut claims = self.hostname_claims.lock().await;
-            claims.clear();
-        }
+        // Conflicts that already existed were reported, e.g. before reconnecting
+        let previous_claims: HashMap<String, (String, String)> =
+            self.hostname_claims.lock().await.clone();
+        let previously_active: HashSet<String> = self
+            .active_containers
+            .lock()
+            .await
+            .keys()
+            .cloned()
+            .collect();
 
         // Inspect all containers, collecting start times for deterministic ordering
         let mut inspected: Vec<(Option<String>, String, ContainerInfo)> = Vec::new();
@@ -102,25 +507,279 @@ impl Synchronizer {
             a.as_deref().unwrap_or("").cmp(b.as_deref().unwrap_or(""))
         });
 
-        // Claim hostnames and populate active containers in start-time order
+        // Claim hostnames and populate active containers in start-time order.
+        // Built aside and swapped in at once, as events and writes carry on
+        // meanwhile and must never see a partial state.
+        let mut active = HashMap::new();
+        let mut claims = HashMap::new();
+        let mut wildcard_claims = HashSet::new();
         for (_, id, info) in inspected {
             let short_id = id.get(..12).unwrap_or(&id);
             debug!("Adding container: {} ({})", info.name, short_id);
-            self.claim_hostnames(&id, &info).await;
-            let mut active = self.active_containers.lock().await;
+            let was_active = previously_active.contains(&id);
+            let previous = &previous_claims;
+            self.claim_in(
+                &mut claims,
+                &mut wildcard_claims,
+                &id,
+                &info,
+                move |hostname, owner_id| {
+                    was_active
+                        && previous
+                            .get(hostname)
+                            .is_some_and(|(previous_owner, _)| previous_owner == owner_id)
+                },
+            );
             active.insert(id, info);
         }
 
-        self.write_hosts_file_immediate().await?;
+        let mut active_containers = self.active_containers.lock().await;
+        let mut hostname_claims = self.hostname_claims.lock().await;
+        let mut current_wildcard_claims = self.wildcard_claims.lock().await;
+        *active_containers = active;
+        *hostname_claims = claims;
+        *current_wildcard_claims = wildcard_claims;
+        drop(current_wildcard_claims);
+        drop(hostname_claims);
+        drop(active_containers);
 
         Ok(())
     }
 
+    /// Describes every active container: its networks, the hostnames it owns
+    /// and the ones it doesn't get, with the reason. Sorted by container name.
+    pub async fn status(&self) -> Vec<ContainerStatus> {
+        let active_containers: HashMap<String, ContainerInfo> =
+            self.active_containers.lock().await.clone();
+        let claims: HashMap<String, (String, String)> = self.hostname_claims.lock().await.clone();
+
+        let mut statuses: Vec<ContainerStatus> = active_containers
+            .into_iter()
+            .map(|(id, container)| {
+                let mut networks: Vec<(String, String)> = container
+                    .networks
+                    .iter()
+                    .map(|(name, info)| (name.clone(), info.ip_address.clone()))
+                    .collect();
+                networks.sort();
+
+                let mut hostnames = Vec::new();
+                let mut skipped = Vec::new();
+                for (ip, hosts) in container.get_hostnames(&self.tld) {
+                    for hostname in hosts {
+                        match Skip::check(&hostname, &id, &claims) {
+                            Some(reason) => skipped.push((ip.clone(), hostname, reason)),
+                            None => hostnames.push((ip.clone(), hostname)),
+                        }
+                    }
+                }
+
+                ContainerStatus {
+                    id,
+                    name: container.name,
+                    networks,
+                    hostnames,
+                    skipped,
+                }
+            })
+            .collect();
+        statuses.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+        statuses
+    }
+
+    /// Explains which running containers generate `hostname`, through which
+    /// rules, and which of them gets it. Owners come first. Nothing is written.
+    pub async fn resolve(&self, hostname: &str) -> Result<Vec<Candidate>> {
+        self.load().await?;
+        let claims: HashMap<String, (String, String)> = self.hostname_claims.lock().await.clone();
+
+        // Without the container filter, to report the containers it leaves out
+        let containers = self
+            .docker
+            .list_containers(Some(ListContainersOptions {
+                all: false,
+                ..Default::default()
+            }))
+            .await
+            .context("Failed to list containers")?;
+
+        let mut candidates = Vec::new();
+        for id in containers.into_iter().filter_map(|c| c.id) {
+            match self
+                .docker
+                .inspect_container(&id, None::<InspectContainerOptions>)
+                .await
+            {
+                Ok(container) => candidates.extend(self.explain(&container, hostname, &claims)),
+                Err(e) => warn!("Failed to inspect container {}: {}", id, e),
+            }
+        }
+        candidates.sort_by(|a, b| {
+            (a.verdict != Verdict::Owns, &a.name, &a.ip).cmp(&(
+                b.verdict != Verdict::Owns,
+                &b.name,
+                &b.ip,
+            ))
+        });
+        Ok(candidates)
+    }
+
+    /// Finds the hostnames `container` generates that match `hostname`,
+    /// ignoring filters and exclusions, and tells why each is or isn't
+    /// published. A generated name with an unresolved variable or a wildcard
+    /// matches any value in its place.
+    fn explain(
+        &self,
+        container: &ContainerInspectResponse,
+        hostname: &str,
+        claims: &HashMap<String, (String, String)>,
+    ) -> Vec<Candidate> {
+        let Some(mut info) =
+            Self::extract_container_info(container.clone(), &NetworkFilter::default())
+        else {
+            return Vec::new();
+        };
+        // Still explained, as a common reason for a missing name (e.g.
+        // `network_mode: host`): the domain names it is configured with,
+        // without an IP to publish them on
+        let exposed = info.has_exposed_ports();
+        let generated_hostnames: Vec<(Option<String>, Vec<String>)> = if exposed {
+            info.get_hostnames(&self.tld)
+                .into_iter()
+                .map(|(ip, hosts)| (Some(ip), hosts))
+                .collect()
+        } else {
+            vec![(None, info.domain_hostnames())]
+        };
+        let excluded = std::mem::take(&mut info.exclude_names);
+        let published: HashSet<(String, String)> =
+            Self::extract_container_info(container.clone(), &self.network_filter)
+                .map(|published_info| published_info.get_hostnames(&self.tld))
+                .unwrap_or_default()
+                .into_iter()
+                .flat_map(|(ip, hosts)| hosts.into_iter().map(move |h| (ip.clone(), h)))
+                .collect();
+        let matches_filter = self.container_filter.matches(container);
+
+        let mut candidates = Vec::new();
+        for (ip, hosts) in generated_hostnames {
+            for generated in hosts {
+                let pattern = template_placeholders_as_globs(&generated);
+                if generated != hostname && !glob_match(&pattern, hostname) {
+                    continue;
+                }
+                let verdict = if !exposed {
+                    Verdict::NotExposed
+                } else if !matches_filter {
+                    Verdict::Filtered("doesn't match --filter")
+                } else if excluded.contains(&generated) {
+                    Verdict::Filtered(EXCLUDE_NAMES_LABEL)
+                } else if !ip.as_ref().is_some_and(|address| {
+                    published.contains(&(address.clone(), generated.clone()))
+                }) {
+                    Verdict::Filtered("network left out by --include-network/--exclude-network")
+                } else {
+                    Skip::check(&generated, &info.id, claims)
+                        .map_or(Verdict::Owns, Verdict::Skipped)
+                };
+                candidates.push(Candidate {
+                    id: info.id.clone(),
+                    name: info.name.clone(),
+                    ip: ip.clone(),
+                    rules: Self::rules(container, &info, &generated, &self.tld),
+                    hostname: generated,
+                    verdict,
+                });
+            }
+        }
+        candidates
+    }
+
+    /// Lists the rules through which `info`, extracted from `container`,
+    /// generates `hostname`.
+    fn rules(
+        container: &ContainerInspectResponse,
+        info: &ContainerInfo,
+        hostname: &str,
+        tld: &str,
+    ) -> Vec<Rule> {
+        let mut rules = Vec::new();
+        let effective_tld = info.tld.as_deref().unwrap_or(tld);
+        if info
+            .networks
+            .keys()
+            .any(|net| format!("{}.{net}", info.name) == hostname)
+            || ((info.ip_address.is_some() || info.tld.is_some())
+                && format!("{}{effective_tld}", info.name) == hostname)
+        {
+            rules.push(Rule::ContainerName);
+        }
+        for (network_name, network) in &info.networks {
+            for alias in &network.aliases {
+                let rule = Rule::Alias(alias.clone());
+                if *alias != info.name
+                    && format!("{alias}.{network_name}") == hostname
+                    && !rules.contains(&rule)
+                {
+                    rules.push(rule);
+                }
+            }
+        }
+
+        // Render each domain list on its own to see which one produced it
+        let (vars, labels) = Self::template_inputs(container.config.as_ref(), &info.name);
+        let ctx = Self::template_context(&vars, &labels, &info.name);
+        let mut network_names: Vec<&str> = info.networks.keys().map(String::as_str).collect();
+        network_names.sort_unstable();
+        let mut bare = info.clone();
+        bare.domain_names.clear();
+        for network in bare.networks.values_mut() {
+            network.aliases.clear();
+        }
+        for (source, raw) in Self::domain_sources(&vars, &labels) {
+            let probe = ContainerInfo {
+                domain_names: Self::render_domain_list(raw, &ctx, &network_names, &info.name),
+                ..bare.clone()
+            };
+            let generates = |c: &ContainerInfo| {
+                if c.has_exposed_ports() {
+                    c.get_hostnames(tld)
+                        .iter()
+                        .any(|(_, hosts)| hosts.iter().any(|h| h == hostname))
+                } else {
+                    c.domain_hostnames().iter().any(|h| h == hostname)
+                }
+            };
+            if generates(&probe) && !generates(&bare) {
+                rules.push(Rule::Domain {
+                    source,
+                    raw: raw.to_string(),
+                });
+            }
+        }
+        rules
+    }
+
     fn schedule_write(&self) {
+        self.metrics
+            .write_requests
+            .fetch_add(1, atomic::Ordering::Relaxed);
+        self.write_pending.store(true, atomic::Ordering::Relaxed);
         self.write_notify.notify_one();
     }
 
+    /// Performs a scheduled write right away instead of waiting out the
+    /// debounce, so it isn't lost on shutdown.
+    pub async fn flush(&self) -> Result<()> {
+        if self.write_pending.load(atomic::Ordering::Relaxed) {
+            debug!("Flushing pending write");
+            self.write_hosts_file_immediate().await?;
+        }
+        Ok(())
+    }
+
     async fn process_pending_writes(&self) -> Result<()> {
+        let mut retry_delay = WRITE_RETRY_MIN_DELAY;
         loop {
             // Idle until the first event signals a pending write
             self.write_notify.notified().await;
@@ -133,8 +792,27 @@ impl Synchronizer {
 
                 tokio::select! {
                     () = sleep(Duration::from_millis(self.debounce_ms)) => {
-                        self.write_hosts_file_immediate().await?;
-                        break;
+                        match self.write_hosts_file_immediate().await {
+                            Ok(()) => {
+                                self.metrics.debounced_writes.fetch_add(1, atomic::Ordering::Relaxed);
+                                retry_delay = WRITE_RETRY_MIN_DELAY;
+                                break;
+                            }
+                            // Reported by /readyz until a retry succeeds
+                            Err(e) => {
+                                error!(
+                                    "Failed to update hosts file, retrying in {}s: {:#}",
+                                    retry_delay.as_secs(),
+                                    e
+                                );
+                                // Still pending, so a shutdown flushes it
+                                self.write_pending.store(true, atomic::Ordering::Relaxed);
+                                sleep(retry_delay).await;
+                                retry_delay = (retry_delay * 2).min(WRITE_RETRY_MAX_DELAY);
+                                // Back to the debounce timer, without counting
+                                // the retry as a new write request
+                            }
+                        }
                     }
                     () = notified => {
                         // New event during debounce window — loop resets the timer
@@ -145,40 +823,242 @@ impl Synchronizer {
     }
 
     pub async fn listen_events(&self) -> Result<()> {
-        let mut filters = HashMap::new();
-        filters.insert(
-            "type".to_string(),
-            vec!["container".to_string(), "network".to_string()],
-        );
+        tokio::select! {
+            result = self.follow_events() => result,
+            result = self.process_pending_writes() => result,
+            result = self.watch_hosts_file(), if self.write_enabled => result,
+            () = self.watchdog() => Ok(()),
+        }
+    }
 
-        let mut events = self.docker.events(Some(EventsOptions {
-            filters: Some(filters),
-            ..Default::default()
-        }));
+    /// Pings the systemd watchdog while the event loop is healthy, so a hung
+    /// daemon gets restarted. Waiting for Docker to come back is healthy: a
+    /// restart wouldn't bring it back any sooner.
+    async fn watchdog(&self) {
+        let Some(interval) = self
+            .notifier
+            .as_ref()
+            .and_then(|_| Notifier::watchdog_interval())
+        else {
+            return std::future::pending().await;
+        };
+        loop {
+            sleep(interval).await;
+            if self.event_loop_healthy() {
+                self.notify("WATCHDOG=1");
+            }
+        }
+    }
 
-        tokio::select! {
-            result = async {
+    /// Following the event stream, or waiting out the delay before reconnecting.
+    fn event_loop_healthy(&self) -> bool {
+        self.health.liveness().is_ok() || self.reconnecting.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Handles Docker events, reconnecting with a growing delay whenever the
+    /// stream breaks (e.g. the daemon restarts). Events missed in between are
+    /// made up for by reloading the containers and resuming the stream where
+    /// it broke off.
+    async fn follow_events(&self) -> Result<()> {
+        let mut delay = RECONNECT_MIN_DELAY;
+        // Unix time in nanoseconds to resume from after reconnecting, so events
+        // Docker sent meanwhile aren't lost. Replaying one twice is harmless.
+        let mut resume: Option<i64> = None;
+        loop {
+            let subscribed = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |now| i64::try_from(now.as_nanos()).unwrap_or(i64::MAX));
+            let since = resume.map(|nanos| {
+                format!(
+                    "{}.{:09}",
+                    nanos.div_euclid(1_000_000_000),
+                    nanos.rem_euclid(1_000_000_000)
+                )
+            });
+            let mut last_event = None;
+            let mut events = self.event_stream(since.as_deref());
+            // The stream only connects once polled, so it's reported connected
+            // once Docker answers a ping rather than right away
+            let answering = match self.docker.ping().await {
+                Ok(_) => true,
+                Err(e) => {
+                    error!("Docker isn't answering: {}", e);
+                    false
+                }
+            };
+            self.health.set_event_stream(answering);
+            self.reconnecting
+                .store(!answering, atomic::Ordering::Relaxed);
+            if answering {
                 while let Some(event_result) = events.next().await {
                     match event_result {
                         Ok(event) => {
+                            last_event = event
+                                .time_nano
+                                .or_else(|| event.time.map(|secs| secs * 1_000_000_000))
+                                .or(last_event);
                             if let Err(e) = self.handle_event(event).await {
                                 error!("Error handling event: {}", e);
                             }
                         }
                         Err(e) => {
                             error!("Error receiving event: {}", e);
+                            break;
                         }
                     }
                 }
-                Ok(())
-            } => result,
-            result = self.process_pending_writes() => result,
+            }
+            drop(events);
+            self.health.set_event_stream(false);
+            self.reconnecting.store(true, atomic::Ordering::Relaxed);
+            resume = last_event
+                .map(|nanos| nanos + 1)
+                .or(resume)
+                .or(Some(subscribed));
+
+            warn!(
+                "Docker event stream closed, reconnecting in {}s",
+                delay.as_secs()
+            );
+            sleep(delay).await;
+            self.metrics
+                .reconnects
+                .fetch_add(1, atomic::Ordering::Relaxed);
+            match self.load().await {
+                Ok(()) => {
+                    info!("Reconnected to Docker");
+                    self.schedule_write();
+                    delay = RECONNECT_MIN_DELAY;
+                }
+                Err(e) => {
+                    warn!("Failed to reload containers: {:#}", e);
+                    delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+                }
+            }
+        }
+    }
+
+    /// Subscribes to container and network events, starting at `since` (a
+    /// Unix timestamp) if given.
+    fn event_stream(
+        &self,
+        since: Option<&str>,
+    ) -> impl tokio_stream::Stream<Item = Result<EventMessage, bollard::errors::Error>> + '_ {
+        // Container events can be narrowed down by Docker itself. Network events
+        // can't (their actor is the network, not the container), so they stay
+        // unfiltered and the container is checked after inspecting it instead.
+        let mut container_filters = self.container_filter.event_filters();
+        container_filters.insert("type".to_string(), vec!["container".to_string()]);
+        let mut network_filters = HashMap::new();
+        network_filters.insert("type".to_string(), vec!["network".to_string()]);
+
+        let container_events = self.docker.events(Some(EventsOptions {
+            since: since.map(str::to_string),
+            filters: Some(container_filters),
+            ..Default::default()
+        }));
+        let network_events = self.docker.events(Some(EventsOptions {
+            since: since.map(str::to_string),
+            filters: Some(network_filters),
+            ..Default::default()
+        }));
+        container_events.merge(network_events)
+    }
+
+    /// Removes this instance's managed section from the hosts file, e.g. on
+    /// shutdown so stale entries don't point at IPs that get reused later.
+    pub async fn cleanup(&self) -> Result<()> {
+        let backups = self.backups.as_ref().map(|(backups, _)| backups);
+        if hosts_file::remove_section(&self.hosts_file, &self.markers, backups, self.lock_timeout)
+            .await?
+        {
+            info!(path = %self.hosts_file.display(), "Removed managed section");
+        }
+        Ok(())
+    }
+
+    /// Backs up the hosts file's current `content` before it is modified, if
+    /// configured. A failed backup is only a warning: the hosts file may be
+    /// writable while its directory isn't, e.g. a bind-mounted `/etc/hosts`.
+    fn back_up(&self, content: &str) {
+        let Some((backups, when)) = &self.backups else {
+            return;
+        };
+        if self.backed_up.swap(true, atomic::Ordering::Relaxed) && *when == BackupWhen::FirstWrite {
+            return;
+        }
+        match backups.save(content) {
+            Ok(backup) => debug!("Backed up hosts file to {}", backup.display()),
+            Err(e) => warn!("{e:#}, writing the hosts file without a backup"),
+        }
+    }
+
+    /// Watches the hosts file and restores the managed section as soon as
+    /// something else edits it. The parent directory is watched because editors
+    /// often replace the file instead of writing to it.
+    async fn watch_hosts_file(&self) -> Result<()> {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let file_name = self.hosts_file.file_name().map(ToOwned::to_owned);
+        let mut watcher =
+            notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+                if let Ok(event) = result {
+                    if !event.kind.is_access()
+                        && event
+                            .paths
+                            .iter()
+                            .any(|path| path.file_name() == file_name.as_deref())
+                    {
+                        // The receiver only goes away on shutdown
+                        tx.send(()).ok();
+                    }
+                }
+            })
+            .context("Failed to create hosts file watcher")?;
+        let directory = self
+            .hosts_file
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or_else(|| std::path::Path::new("."));
+        notify::Watcher::watch(&mut watcher, directory, notify::RecursiveMode::NonRecursive)
+            .with_context(|| format!("Failed to watch {}", directory.display()))?;
+        debug!("Watching {} for external edits", self.hosts_file.display());
+
+        while rx.recv().await.is_some() {
+            if let Err(e) = self.reconcile_hosts_file().await {
+                error!("Error checking hosts file: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrites the managed section right away if it no longer matches what
+    /// was last written. Our own writes match, so they are ignored.
+    async fn reconcile_hosts_file(&self) -> Result<()> {
+        let Some(expected) = *self.last_section.lock().await else {
+            return Ok(());
+        };
+        // Read under the lock, so a write in progress isn't taken for an edit
+        let content = LockedFile::open(&self.hosts_file, self.lock_timeout)
+            .await?
+            .read()?;
+        if hosts_file::section_checksum(&content, &self.markers) == expected {
+            return Ok(());
         }
+
+        warn!(
+            "Managed section of {} was modified externally, restoring it",
+            self.hosts_file.display()
+        );
+        self.write_hosts_file_immediate().await
     }
 
     async fn handle_event(&self, event: EventMessage) -> Result<()> {
         let action = event.action.as_deref().unwrap_or("");
         let event_type = event.typ;
+        self.metrics.record_event(
+            &event_type.map(|t| t.to_string()).unwrap_or_default(),
+            action,
+        );
         let actor_ref = event.actor.as_ref();
         let actor_id = actor_ref.and_then(|a| a.id.as_deref()).unwrap_or("");
 
@@ -234,33 +1114,53 @@ impl Synchronizer {
             if !info.has_exposed_ports() {
                 return Ok(());
             }
-            let short_actor_id = container_id.get(..12).unwrap_or(container_id);
-            println!(
-                "{} Container {} ({})",
-                "▶".bright_green(),
-                info.name.bright_white(),
-                short_actor_id.bright_black()
-            );
-            self.claim_hostnames(container_id, &info).await;
-            let mut active = self.active_containers.lock().await;
-            active.insert(container_id.to_string(), info);
-            drop(active);
-            self.schedule_write();
+            if self.output == OutputFormat::Json {
+                output::emit(&Event::ContainerUp {
+                    id: container_id,
+                    name: &info.name,
+                    ip_address: info.ip_address.as_deref(),
+                    networks: info
+                        .networks
+                        .iter()
+                        .map(|(name, network)| (name.as_str(), network.ip_address.as_str()))
+                        .collect(),
+                });
+            }
+            self.add_container(container_id, info).await;
         }
         Ok(())
     }
 
+    /// Claims the hostnames of a started container and schedules a write.
+    async fn add_container(&self, container_id: &str, info: ContainerInfo) {
+        let hostnames = self.claim_hostnames(container_id, &info).await;
+        info!(
+            container_id = container_id.get(..12).unwrap_or(container_id),
+            name = %info.name,
+            hostnames = %hostnames.join(","),
+            "Container started"
+        );
+        let mut active = self.active_containers.lock().await;
+        active.insert(container_id.to_string(), info);
+        drop(active);
+        self.schedule_write();
+    }
+
     async fn handle_container_down(&self, container_id: &str) {
         let mut active = self.active_containers.lock().await;
         if let Some(info) = active.remove(container_id) {
             drop(active);
             self.release_hostnames(container_id, &info, None).await;
-            let short_actor_id = container_id.get(..12).unwrap_or(container_id);
-            println!(
-                "{} Container {} ({})",
-                "■".bright_red(),
-                info.name.bright_white(),
-                short_actor_id.bright_black()
+            if self.output == OutputFormat::Json {
+                output::emit(&Event::ContainerDown {
+                    id: container_id,
+                    name: &info.name,
+                });
+            }
+            info!(
+                container_id = container_id.get(..12).unwrap_or(container_id),
+                name = %info.name,
+                "Container stopped"
             );
             self.schedule_write();
         }
@@ -278,6 +1178,15 @@ impl Synchronizer {
             active.get(container_id).cloned()
         };
         let Some(info) = snapshot else { return };
+        // Networks excluded by the network filter aren't in the snapshot and
+        // never published anything
+        if !info.networks.contains_key(network_name) {
+            debug!(
+                "Ignoring disconnect of \"{}\" from unpublished network \"{}\"",
+                info.name, network_name
+            );
+            return;
+        }
 
         self.release_hostnames(container_id, &info, Some(network_name))
             .await;
@@ -309,6 +1218,8 @@ impl Synchronizer {
         Some((container_id, network_name))
     }
 
+    /// Inspects a container, returning `None` when it doesn't match the
+    /// container filter or has nothing to publish.
     async fn inspect_container(&self, id: &str) -> Result<Option<ContainerInfo>> {
         let container = self
             .docker
@@ -316,10 +1227,20 @@ impl Synchronizer {
             .await
             .context("Failed to inspect container")?;
 
-        Ok(Self::extract_container_info(container))
+        if !self.container_filter.matches(&container) {
+            debug!("Ignoring container {}: doesn't match filters", id);
+            return Ok(None);
+        }
+        Ok(Self::extract_container_info(
+            container,
+            &self.network_filter,
+        ))
     }
 
-    fn extract_container_info(container: ContainerInspectResponse) -> Option<ContainerInfo> {
+    fn extract_container_info(
+        container: ContainerInspectResponse,
+        network_filter: &NetworkFilter,
+    ) -> Option<ContainerInfo> {
         let id = container.id?;
         let name = container.name?.trim_start_matches('/').to_string();
 
@@ -335,9 +1256,33 @@ impl Synchronizer {
             return None;
         }
 
+        let (vars, labels) = Self::template_inputs(container.config.as_ref(), &name);
+        let ctx = Self::template_context(&vars, &labels, &name);
+
+        // Networks to publish on; all of them unless the label narrows it down
+        let published_on: Option<Vec<&str>> = labels.get(NETWORKS_LABEL).map(|list| {
+            list.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .collect()
+        });
+        let extra_aliases = labels
+            .get(ALIASES_LABEL)
+            .map(|raw| Self::render_domain_list(raw, &ctx, &[], &name))
+            .unwrap_or_default();
+
         let mut networks = HashMap::new();
         if let Some(nets) = network_settings.networks {
             for (network_name, network) in nets {
+                // Excluded networks never get a NetworkInfo, so they can't claim hostnames
+                let label_allows = published_on.as_ref().is_none_or(|wanted| {
+                    wanted
+                        .iter()
+                        .any(|w| network_matches(&network_name, w) || glob_match(w, &network_name))
+                });
+                if !label_allows || !network_filter.allows(&network_name) {
+                    continue;
+                }
                 if let Some(ip) = network.ip_address {
                     if !ip.is_empty() {
                         let mut aliases = network.aliases.unwrap_or_default();
@@ -345,111 +1290,47 @@ impl Synchronizer {
                         if !aliases.contains(&name) {
                             aliases.push(name.clone());
                         }
+                        for alias in &extra_aliases {
+                            if !aliases.contains(alias) {
+                                aliases.push(alias.clone());
+                            }
+                        }
 
                         networks.insert(
                             network_name,
                             NetworkInfo {
                                 ip_address: ip,
                                 aliases,
+                                gateway: network.gateway.filter(|g| !g.is_empty()),
                             },
                         );
                     }
                 }
             }
         }
+        // Sorted so domain names rendered per network come out in a stable order
+        let mut network_names: Vec<&str> = networks.keys().map(String::as_str).collect();
+        network_names.sort_unstable();
 
-        // Build variables map from environment variables and specific labels
-        let mut vars = HashMap::new();
-        let mut compose_project: Option<String> = None;
-
-        if let Some(config) = &container.config {
-            if let Some(env_vars) = &config.env {
-                for env_var in env_vars {
-                    if let Some((k, v)) = env_var.split_once('=') {
-                        vars.insert(k.to_string(), v.to_string());
-                    }
-                }
-            }
-            if let Some(labels) = &config.labels {
-                if let Some(proj) = labels.get("com.docker.compose.project") {
-                    compose_project = Some(proj.clone());
-                }
-            }
-        }
-
-        if !vars.contains_key("COMPOSE_PROJECT_NAME") {
-            let proj_name = compose_project
-                .unwrap_or_else(|| name.split('-').next().unwrap_or(&name).to_string());
-            vars.insert("COMPOSE_PROJECT_NAME".to_string(), proj_name);
+        let mut domain_names = Vec::new();
+        for (_, raw) in Self::domain_sources(&vars, &labels) {
+            domain_names.extend(Self::render_domain_list(raw, &ctx, &network_names, &name));
         }
 
-        let replace_vars = |input: &str, vars: &HashMap<String, String>| -> String {
-            let mut result = String::with_capacity(input.len());
-            let mut chars = input.chars().peekable();
-            while let Some(c) = chars.next() {
-                if c == '{' {
-                    let mut var_name = String::new();
-                    let mut closed = false;
-                    while let Some(&next_c) = chars.peek() {
-                        if next_c == '}' {
-                            closed = true;
-                            chars.next(); // consume '}'
-                            break;
-                        } else if next_c == '{' {
-                            break;
-                        }
-                        var_name.push(next_c);
-                        chars.next();
-                    }
-                    if closed && !var_name.is_empty() {
-                        if let Some(val) = vars.get(&var_name) {
-                            result.push_str(val);
-                        } else {
-                            result.push('{');
-                            result.push_str(&var_name);
-                            result.push('}');
-                        }
-                    } else {
-                        result.push('{');
-                        result.push_str(&var_name);
-                    }
-                } else {
-                    result.push(c);
-                }
-            }
-            result
-        };
-
-        // Extract DOMAIN_NAME environment variable
-        let mut domain_names = Vec::new();
-        if let Some(config) = container.config {
-            if let Some(env_vars) = config.env {
-                for env in env_vars {
-                    if let Some(domain_value) = env.strip_prefix("DOMAIN_NAME=") {
-                        let replaced_domain = replace_vars(domain_value, &vars);
-                        domain_names.extend(
-                            replaced_domain
-                                .split(',')
-                                .map(|s| s.trim().to_string())
-                                .filter(|s| !s.is_empty()),
-                        );
-                    }
-                }
-            }
-
-            // Extract dev.orbstack.domains label
-            if let Some(labels) = config.labels {
-                if let Some(orbstack_domains) = labels.get("dev.orbstack.domains") {
-                    let replaced_domains = replace_vars(orbstack_domains, &vars);
-                    domain_names.extend(
-                        replaced_domains
-                            .split(',')
-                            .map(|s| s.trim().to_string())
-                            .filter(|s| !s.is_empty()),
-                    );
-                }
-            }
-        }
+        // Subdomains that wildcard domains (`*.app.test`) expand to
+        let subdomains = labels
+            .get(SUBDOMAINS_LABEL)
+            .map(|raw| Self::render_domain_list(raw, &ctx, &[], &name))
+            .unwrap_or_default();
+
+        let exclude_names = labels
+            .get(EXCLUDE_NAMES_LABEL)
+            .map(|raw| Self::render_domain_list(raw, &ctx, &[], &name))
+            .unwrap_or_default();
+        let tld = labels
+            .get(TLD_LABEL)
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty());
 
         Some(ContainerInfo {
             id,
@@ -457,21 +1338,162 @@ impl Synchronizer {
             ip_address: None,
             networks,
             domain_names,
+            subdomains,
+            exclude_names,
+            tld,
+            project: labels.get("com.docker.compose.project").cloned(),
             running,
         })
     }
 
+    /// Environment variables and labels of a container, for rendering templates.
+    /// `COMPOSE_PROJECT_NAME` falls back to the compose label, then to the
+    /// container name up to the first `-`.
+    fn template_inputs(
+        config: Option<&ContainerConfig>,
+        name: &str,
+    ) -> (HashMap<String, String>, HashMap<String, String>) {
+        let mut vars: HashMap<String, String> = config
+            .and_then(|c| c.env.as_ref())
+            .into_iter()
+            .flatten()
+            .filter_map(|env_var| env_var.split_once('='))
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let labels = config.and_then(|c| c.labels.clone()).unwrap_or_default();
+
+        if !vars.contains_key("COMPOSE_PROJECT_NAME") {
+            let proj_name = labels
+                .get("com.docker.compose.project")
+                .cloned()
+                .unwrap_or_else(|| name.split('-').next().unwrap_or(name).to_string());
+            vars.insert("COMPOSE_PROJECT_NAME".to_string(), proj_name);
+        }
+        (vars, labels)
+    }
+
+    fn template_context<'a>(
+        vars: &'a HashMap<String, String>,
+        labels: &'a HashMap<String, String>,
+        name: &'a str,
+    ) -> template::Context<'a> {
+        template::Context {
+            env: vars,
+            labels,
+            service: labels
+                .get("com.docker.compose.service")
+                .map_or(name, String::as_str),
+            network: None,
+        }
+    }
+
+    /// Raw domain name lists of a container, with where each comes from. The
+    /// native domains label takes precedence over the `DOMAIN_NAME` env var,
+    /// keeping hosting concerns out of the application's environment.
+    fn domain_sources<'a>(
+        vars: &'a HashMap<String, String>,
+        labels: &'a HashMap<String, String>,
+    ) -> Vec<(&'static str, &'a str)> {
+        let domains = labels
+            .get(DOMAINS_LABEL)
+            .map(|raw| (DOMAINS_LABEL, raw))
+            .or_else(|| vars.get("DOMAIN_NAME").map(|raw| ("DOMAIN_NAME", raw)));
+        let orbstack = labels
+            .get(ORBSTACK_DOMAINS_LABEL)
+            .map(|raw| (ORBSTACK_DOMAINS_LABEL, raw));
+        [domains, orbstack]
+            .into_iter()
+            .flatten()
+            .map(|(source, raw)| (source, raw.as_str()))
+            .collect()
+    }
+
+    /// Renders a comma-separated list of domain templates. Templates using
+    /// `{network}` are rendered once per network and bound to it with the
+    /// `network:hostname` form. Malformed templates are reported and dropped.
+    fn render_domain_list(
+        raw: &str,
+        ctx: &template::Context<'_>,
+        network_names: &[&str],
+        container_name: &str,
+    ) -> Vec<String> {
+        let rendered: Result<Vec<(Option<&str>, String)>, TemplateError> =
+            if template::uses_network(raw) {
+                network_names
+                    .iter()
+                    .map(|net| {
+                        let per_network = template::Context {
+                            network: Some(net),
+                            ..*ctx
+                        };
+                        template::render(raw, &per_network).map(|value| (Some(*net), value))
+                    })
+                    .collect()
+            } else {
+                template::render(raw, ctx).map(|value| vec![(None, value)])
+            };
+
+        match rendered {
+            Ok(values) => values
+                .into_iter()
+                .flat_map(|(network, value)| {
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|s| !s.is_empty())
+                        .map(|domain| match network {
+                            Some(net) if !domain.contains(':') => format!("{net}:{domain}"),
+                            _ => domain.to_string(),
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect(),
+            Err(e) => {
+                warn!(
+                    "Container \"{}\": invalid domain template \"{}\": {}",
+                    container_name, raw, e
+                );
+                Vec::new()
+            }
+        }
+    }
+
     /// Attempts to claim all hostnames generated by `container`. The first container
     /// to claim a hostname owns it until it stops. Warns once on conflict.
-    async fn claim_hostnames(&self, container_id: &str, container: &ContainerInfo) {
+    /// Returns the hostnames it got.
+    async fn claim_hostnames(&self, container_id: &str, container: &ContainerInfo) -> Vec<String> {
+        let mut claims = self.hostname_claims.lock().await;
+        let mut wildcard_claims = self.wildcard_claims.lock().await;
+        self.claim_in(
+            &mut claims,
+            &mut wildcard_claims,
+            container_id,
+            container,
+            |_, _| false,
+        )
+    }
+
+    /// Claims the hostnames of `container` in `claims` and `wildcard_claims`,
+    /// as [`Self::claim_hostnames`] does. Conflicts with an owner for which
+    /// `reported(hostname, owner_id)` holds were reported before, and aren't
+    /// counted or warned about again.
+    fn claim_in(
+        &self,
+        claims: &mut HashMap<String, (String, String)>,
+        wildcard_claims: &mut HashSet<String>,
+        container_id: &str,
+        container: &ContainerInfo,
+        reported: impl Fn(&str, &str) -> bool,
+    ) -> Vec<String> {
         let all_hostnames: Vec<String> = container
             .get_hostnames(&self.tld)
             .into_iter()
             .flat_map(|(_, hosts)| hosts)
             .collect();
 
-        let mut claims = self.hostname_claims.lock().await;
+        let mut claimed = Vec::new();
         for hostname in all_hostnames {
+            let from_wildcard = container.is_wildcard_hostname(&hostname);
             match claims.entry(hostname.clone()) {
                 std::collections::hash_map::Entry::Vacant(e) => {
                     debug!(
@@ -479,16 +1501,62 @@ impl Synchronizer {
                         container.name, hostname
                     );
                     e.insert((container_id.to_string(), container.name.clone()));
+                    if from_wildcard {
+                        wildcard_claims.insert(hostname.clone());
+                    }
+                    claimed.push(hostname);
                 }
-                std::collections::hash_map::Entry::Occupied(e) => {
+                // An exact name always beats a wildcard expansion, regardless of
+                // which container claimed first.
+                std::collections::hash_map::Entry::Occupied(mut e)
+                    if !from_wildcard
+                        && wildcard_claims.contains(&hostname)
+                        && e.get().0 != container_id =>
+                {
                     let (_, owner_name) = e.get();
+                    warn!(
+                        "Hostname \"{}\" taken over from wildcard claim by \"{}\" for \"{}\"",
+                        hostname, owner_name, container.name
+                    );
+                    e.insert((container_id.to_string(), container.name.clone()));
+                    wildcard_claims.remove(&hostname);
+                    claimed.push(hostname);
+                }
+                // Already ours, e.g. generated for several IPs or a replayed event
+                std::collections::hash_map::Entry::Occupied(e) if e.get().0 == container_id => {
+                    if !claimed.contains(&hostname) {
+                        claimed.push(hostname);
+                    }
+                }
+                std::collections::hash_map::Entry::Occupied(e) => {
+                    let (owner_id, owner_name) = e.get();
+                    if reported(&hostname, owner_id) {
+                        continue;
+                    }
+                    self.metrics
+                        .conflicts
+                        .fetch_add(1, atomic::Ordering::Relaxed);
                     warn!(
                         "Hostname \"{}\" already claimed by \"{}\", skipping for \"{}\"",
                         hostname, owner_name, container.name
                     );
+                    if self.output == OutputFormat::Json {
+                        output::emit(&Event::Conflict {
+                            hostname: &hostname,
+                            container: output::Container {
+                                id: container_id,
+                                name: &container.name,
+                            },
+                            owner: output::Container {
+                                id: owner_id,
+                                name: owner_name,
+                            },
+                        });
+                    }
                 }
             }
         }
+        claimed
     }
 
     /// Releases hostname claims held by `container_id`. When `only_network` is
@@ -502,8 +1570,14 @@ impl Synchronizer {
         container: &ContainerInfo,
         only_network: Option<&str>,
     ) {
-        let only_network_ip =
-            only_network.and_then(|net| container.networks.get(net).map(|n| n.ip_address.as_str()));
+        let only_network_ip = match only_network {
+            Some(net) => match container.networks.get(net) {
+                Some(network) => Some(network.ip_address.as_str()),
+                // Not a published network, nothing is bound to it
+                None => return,
+            },
+            None => None,
+        };
 
         // When filtering to a specific network, keep only the entry whose IP
         // matches that network's IP. The global-ip entry (used when the container
@@ -517,6 +1591,7 @@ impl Synchronizer {
             .collect();
 
         let mut claims = self.hostname_claims.lock().await;
+        let mut wildcard_claims = self.wildcard_claims.lock().await;
         for hostname in all_hostnames {
             if claims
                 .get(&hostname)
@@ -527,8 +1602,11 @@ impl Synchronizer {
                     container.name, hostname
                 );
                 claims.remove(&hostname);
+                wildcard_claims.remove(&hostname);
             }
         }
+        drop(wildcard_claims);
+        drop(claims);
     }
 
     /// Like `inspect_container` but also returns the container's `started_at` timestamp,
@@ -543,19 +1621,158 @@ impl Synchronizer {
             .await
             .context("Failed to inspect container")?;
         let started_at = container.state.as_ref().and_then(|s| s.started_at.clone());
-        Ok((started_at, Self::extract_container_info(container)))
+        if !self.container_filter.matches(&container) {
+            return Ok((started_at, None));
+        }
+        Ok((
+            started_at,
+            Self::extract_container_info(container, &self.network_filter),
+        ))
     }
 
-    async fn write_hosts_file_immediate(&self) -> Result<()> {
+    /// Builds one entry per network gateway seen on the active containers.
+    /// Names already claimed by a container are left to the container.
+    fn gateway_host_entries(
+        &self,
+        active_containers: &HashMap<String, ContainerInfo>,
+        claims: &HashMap<String, (String, String)>,
+    ) -> Vec<HostEntry> {
+        // BTreeMap keeps networks sorted by name, so the host alias lands on a
+        // predictable gateway.
+        let gateways: BTreeMap<&str, &str> = active_containers
+            .values()
+            .flat_map(|c| &c.networks)
+            .filter_map(|(net, info)| Some((net.as_str(), info.gateway.as_deref()?)))
+            .collect();
+
+        let mut host_alias = self
+            .host_alias
+            .as_deref()
+            .filter(|a| !claims.contains_key(*a));
+        let mut entries = Vec::new();
+        for (network_name, gateway) in gateways {
+            let mut hostnames = Vec::new();
+            let gateway_name = format!("gateway.{network_name}");
+            if !claims.contains_key(&gateway_name) {
+                hostnames.push(gateway_name);
+            }
+            if let Some(alias) = host_alias.take() {
+                hostnames.push(alias.to_string());
+            }
+            if !hostnames.is_empty() {
+                entries.push(HostEntry {
+                    ip: gateway.to_string(),
+                    hostnames,
+                    skipped: Vec::new(),
+                    owner: None,
+                    project: None,
+                    network: Some(network_name.to_string()),
+                });
+            }
+        }
+        entries
+    }
+
+    /// Orders the entries and renders them into the lines of the managed
+    /// section, grouped under `# project: ...`/`# network: ...` headers when
+    /// grouping is enabled.
+    fn render_entries(&self, entries: &[HostEntry]) -> Vec<String> {
+        self.render_section(entries)
+            .into_iter()
+            .map(|(line, _)| line)
+            .collect()
+    }
+
+    /// The lines of the managed section, each with the entry it renders, or
+    /// `None` for a group header.
+    fn render_section<'a>(&self, entries: &'a [HostEntry]) -> Vec<(String, Option<&'a HostEntry>)> {
+        let label = match self.group_by {
+            GroupBy::None => {
+                let mut sorted: Vec<&HostEntry> = entries.iter().collect();
+                sorted.sort_by(|a, b| HostEntry::cmp_by_ip(a, b));
+                return sorted
+                    .into_iter()
+                    .map(|e| (e.render(self.annotate), Some(e)))
+                    .collect();
+            }
+            GroupBy::Project => "project",
+            GroupBy::Network => "network",
+        };
+
+        // Named groups in alphabetical order, entries without one last
+        let mut groups: BTreeMap<(bool, String), Vec<&HostEntry>> = BTreeMap::new();
+        for entry in entries {
+            let key = if self.group_by == GroupBy::Project {
+                entry.project.clone()
+            } else {
+                entry.network.clone()
+            };
+            groups
+                .entry((key.is_none(), key.unwrap_or_else(|| "(none)".to_string())))
+                .or_default()
+                .push(entry);
+        }
+
+        let mut lines = Vec::new();
+        for ((_, name), mut group) in groups {
+            lines.push((format!("# {label}: {name}"), None));
+            group.sort_by(|a, b| HostEntry::cmp_by_hostname(a, b));
+            lines.extend(
+                group
+                    .into_iter()
+                    .map(|e| (e.render(self.annotate), Some(e))),
+            );
+        }
+        lines
+    }
+
+    /// Reports the rendered state as an `entries` event in JSON output mode.
+    fn emit_entries(
+        &self,
+        entries: &[HostEntry],
+        written: bool,
+        container_count: usize,
+        hostname_count: usize,
+    ) {
+        if self.output != OutputFormat::Json {
+            return;
+        }
+        let mut sorted: Vec<&HostEntry> = entries.iter().collect();
+        sorted.sort_by(|a, b| HostEntry::cmp_by_ip(a, b));
+        output::emit(&Event::Entries {
+            written,
+            containers: container_count,
+            hostnames: hostname_count,
+            entries: sorted
+                .into_iter()
+                .map(|entry| output::Entry {
+                    ip: &entry.ip,
+                    hostnames: &entry.hostnames,
+                    skipped: &entry.skipped,
+                    owner: entry
+                        .owner
+                        .as_ref()
+                        .map(|(name, id)| output::Container { id, name }),
+                    project: entry.project.as_deref(),
+                    network: entry.network.as_deref(),
+                })
+                .collect(),
+        });
+    }
+
+    /// Builds the entries of the managed section from the active containers,
+    /// with the number of containers and published hostnames.
+    async fn host_entries(&self) -> (Vec<HostEntry>, usize, usize) {
         // Snapshot both maps so we don't hold locks during file I/O.
         let active_containers: HashMap<String, ContainerInfo> =
             self.active_containers.lock().await.clone();
         let claims: HashMap<String, (String, String)> = self.hostname_claims.lock().await.clone();
 
         // Build new hosts entries, filtering out hostnames claimed by other containers.
-        let mut host_entries_with_ip = Vec::new();
+        let mut entries = Vec::new();
         let mut container_count = 0;
         let mut hostname_count = 0;
+        let mut rejected_count = 0;
 
         for (container_id, container) in &active_containers {
             let hostnames = container.get_hostnames(&self.tld);
@@ -565,158 +1782,177 @@ impl Synchronizer {
                 let mut skipped = Vec::new();
 
                 for h in hosts {
-                    // Unresolved variables e.g. {MISSING_VAR} shouldn't be added as hostnames.
-                    if h.contains('{') && h.contains('}') {
-                        skipped.push(h);
-                    } else if claims
-                        .get(&h)
-                        .is_none_or(|(owner_id, _)| owner_id == container_id)
-                    {
-                        kept.push(h);
-                    } else {
-                        skipped.push(h);
+                    match Skip::check(&h, container_id, &claims) {
+                        Some(Skip::Conflict { .. }) => skipped.push(h),
+                        Some(_) => {
+                            rejected_count += 1;
+                            skipped.push(h);
+                        }
+                        None => kept.push(h),
                     }
                 }
 
-                if kept.is_empty() && !skipped.is_empty() {
-                    // All hostnames for this IP were claimed by another container.
-                    // Write a comment-only line so the skip is visible in the file.
-                    host_entries_with_ip.push((
-                        ip.clone(),
-                        format!(
-                            "# {} ({}): all hostnames skipped: {}",
-                            ip,
-                            container.name,
-                            skipped.join(", ")
-                        ),
-                    ));
-                } else if !kept.is_empty() {
-                    let skip_comment = if skipped.is_empty() {
-                        String::new()
-                    } else {
-                        format!("  # skipped: {}", skipped.join(", "))
-                    };
-                    host_entries_with_ip.push((
-                        ip.clone(),
-                        format!("{} {}{}", ip, kept.join(" "), skip_comment),
-                    ));
-                    hostname_count += kept.len();
+                if kept.is_empty() && skipped.is_empty() {
+                    continue;
                 }
+                hostname_count += kept.len();
+                let network = container
+                    .networks
+                    .iter()
+                    .find(|(_, info)| info.ip_address == ip)
+                    .map(|(name, _)| name.clone());
+                entries.push(HostEntry {
+                    ip,
+                    hostnames: kept,
+                    skipped,
+                    owner: Some((container.name.clone(), container_id.clone())),
+                    project: container.project.clone(),
+                    network,
+                });
             }
 
             container_count += 1;
         }
 
-        host_entries_with_ip.sort_by(|a, b| a.0.cmp(&b.0));
-        let host_entries: Vec<String> = host_entries_with_ip
-            .into_iter()
-            .map(|(_, line)| line)
-            .collect();
+        if self.gateway_entries {
+            entries.extend(self.gateway_host_entries(&active_containers, &claims));
+        }
 
-        // Display the output
-        println!();
-        if self.write_enabled {
-            if host_entries.is_empty() {
-                println!("{} No active containers to write", "→".bright_cyan());
-            } else {
-                println!("{} Hosts entries to be written:", "→".bright_cyan());
-                for line in &host_entries {
-                    println!("  {}", line.bright_white());
-                }
-            }
-        } else if host_entries.is_empty() {
-            println!("{} No active containers", "→".bright_cyan());
-        } else {
-            println!("{} Generated hosts entries:", "→".bright_cyan());
-            for line in &host_entries {
-                println!("  {}", line.bright_white());
-            }
+        let gauge = |value: usize| u64::try_from(value).unwrap_or(u64::MAX);
+        let metrics = &self.metrics;
+        metrics
+            .active_containers
+            .store(gauge(container_count), atomic::Ordering::Relaxed);
+        metrics
+            .hostnames_published
+            .store(gauge(hostname_count), atomic::Ordering::Relaxed);
+        metrics
+            .hostnames_rejected
+            .store(gauge(rejected_count), atomic::Ordering::Relaxed);
+        (entries, container_count, hostname_count)
+    }
+
+    /// Returns the hosts file as it is and as the next write would leave it,
+    /// without locking or modifying it.
+    pub async fn preview(&self) -> Result<(String, String)> {
+        let (entries, _, _) = self.host_entries().await;
+        let content = fs::read_to_string(&self.hosts_file)
+            .with_context(|| format!("Failed to read {}", self.hosts_file.display()))?;
+        let spliced = hosts_file::splice(&content, &self.render_entries(&entries), &self.markers);
+        Ok((content, spliced.content))
+    }
+
+    async fn write_hosts_file_immediate(&self) -> Result<()> {
+        let result = self.write_hosts_file().await;
+        self.health.record_write(&result);
+        if self.notifier.is_some() {
+            self.notify(&match &result {
+                Ok(()) => format!(
+                    "STATUS={} containers, {} hostnames",
+                    self.metrics
+                        .active_containers
+                        .load(atomic::Ordering::Relaxed),
+                    self.metrics
+                        .hostnames_published
+                        .load(atomic::Ordering::Relaxed)
+                ),
+                Err(e) => format!("STATUS=Failed to update hosts file: {e:#}"),
+            });
         }
-        println!();
+        result
+    }
+
+    async fn write_hosts_file(&self) -> Result<()> {
+        let started = Instant::now();
+        self.write_pending.store(false, atomic::Ordering::Relaxed);
+
+        let (entries, container_count, hostname_count) = self.host_entries().await;
+        let section = self.render_section(&entries);
+        let host_entries: Vec<String> = section.iter().map(|(line, _)| line.clone()).collect();
 
         if !self.write_enabled {
-            println!(
-                "{} {} containers, {} hostnames",
-                "ℹ".bright_blue(),
-                container_count.to_string().bright_white(),
-                hostname_count.to_string().bright_white()
+            self.emit_entries(&entries, false, container_count, hostname_count);
+            Self::log_section(&section);
+            info!(
+                containers = container_count,
+                hostnames = hostname_count,
+                "Generated hosts entries"
             );
             return Ok(());
         }
 
         // Write mode: actually update the file
-        let content = fs::read_to_string(&self.hosts_file).context("Failed to read hosts file")?;
-
-        let lines: Vec<&str> = content.lines().collect();
-
-        // Find the managed section
-        let start_idx = lines.iter().position(|line| line.trim() == START_TAG);
-
-        let end_idx = lines.iter().position(|line| line.trim() == END_TAG);
-
-        let mut new_lines = Vec::new();
-
-        match (start_idx, end_idx) {
-            (Some(start), Some(end)) if start < end => {
-                // Managed section exists - replace it
-                if let Some(before_managed) = lines.get(..start) {
-                    new_lines.extend(before_managed.iter().map(std::string::ToString::to_string));
-                }
-
-                if !host_entries.is_empty() {
-                    // Add our managed section
-                    new_lines.push(START_TAG.to_string());
-                    new_lines.extend(host_entries);
-                    new_lines.push(END_TAG.to_string());
-                }
-                // Note: if host_entries is empty, we don't add the tags (removes empty section)
-
-                if end + 1 < lines.len() {
-                    if let Some(after_managed) = lines.get(end + 1..) {
-                        new_lines
-                            .extend(after_managed.iter().map(std::string::ToString::to_string));
-                    }
-                }
-            }
-            _ => {
-                // No valid managed section - append to end
-                new_lines.extend(lines.iter().map(std::string::ToString::to_string));
-
-                if !host_entries.is_empty() {
-                    // Add a blank line before our section if the file doesn't end with one
-                    if let Some(last_line) = new_lines.last() {
-                        if !last_line.is_empty() {
-                            new_lines.push(String::new());
-                        }
-                    }
-
-                    new_lines.push(START_TAG.to_string());
-                    new_lines.extend(host_entries);
-                    new_lines.push(END_TAG.to_string());
-                }
-            }
+        // Hold the lock for the whole read-splice-write cycle
+        let mut file = LockedFile::open(&self.hosts_file, self.lock_timeout).await?;
+        let content = file.read()?;
+
+        let spliced = hosts_file::splice(&content, &host_entries, &self.markers);
+        if spliced.repaired {
+            warn!(
+                "Repaired malformed managed section markers in {}",
+                self.hosts_file.display()
+            );
+        }
+        let new_content = spliced.content;
+        // Only remembered once the file has it, so a failed write doesn't make
+        // every later file event look like an external edit
+        let checksum = hosts_file::section_checksum(&new_content, &self.markers);
+
+        // Leave the file (and its mtime) alone when nothing changed, so file
+        // watchers and dnsmasq don't reload for nothing.
+        if new_content == content {
+            let skipped = self
+                .metrics
+                .skipped_writes
+                .fetch_add(1, atomic::Ordering::Relaxed)
+                + 1;
+            debug!(
+                "Hosts file already up to date, skipping write ({} skipped so far)",
+                skipped
+            );
+            *self.last_section.lock().await = Some(checksum);
+            self.emit_entries(&entries, false, container_count, hostname_count);
+            return Ok(());
         }
 
-        let new_content = new_lines.join("\n") + "\n";
-
-        fs::write(&self.hosts_file, new_content).context("Failed to write hosts file")?;
+        self.back_up(&content);
+        file.write(&new_content)?;
+        drop(file);
+        *self.last_section.lock().await = Some(checksum);
+        self.metrics.record_write(started.elapsed());
 
+        self.emit_entries(&entries, true, container_count, hostname_count);
+        Self::log_section(&section);
         if container_count == 0 {
-            println!(
-                "{} Removed empty managed section from hosts file",
-                "✓".bright_green()
-            );
+            info!(path = %self.hosts_file.display(), "Removed empty managed section");
         } else {
-            println!(
-                "{} Updated hosts file: {} containers, {} hostnames",
-                "✓".bright_green(),
-                container_count.to_string().bright_white(),
-                hostname_count.to_string().bright_white()
+            info!(
+                path = %self.hosts_file.display(),
+                containers = container_count,
+                hostnames = hostname_count,
+                "Updated hosts file"
             );
         }
 
         Ok(())
     }
+
+    /// Logs the managed section line by line, as it is written, with the IP
+    /// and hostnames of each entry as fields to query the JSON output by.
+    fn log_section(lines: &[(String, Option<&HostEntry>)]) {
+        for (line, rendered) in lines {
+            if let Some(entry) = rendered {
+                info!(
+                    ip = %entry.ip,
+                    hostnames = %entry.hostnames.join(","),
+                    "{}",
+                    line
+                );
+            } else {
+                info!("{}", line);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -728,6 +1964,7 @@ impl Synchronizer {
 )]
 mod tests {
     use super::*;
+    use crate::hosts_file::{END_TAG, START_TAG};
     use std::fs;
     use tempfile::NamedTempFile;
 
@@ -751,9 +1988,8 @@ mod tests {
                     id: "test123".to_string(),
                     name: "nginx".to_string(),
                     ip_address: Some("172.17.0.2".to_string()),
-                    networks: HashMap::new(),
-                    domain_names: vec![],
                     running: true,
+                    ..Default::default()
                 },
             );
         }
@@ -791,6 +2027,7 @@ mod tests {
             NetworkInfo {
                 ip_address: "172.18.0.2".to_string(),
                 aliases: vec!["web".to_string()],
+                ..Default::default()
             },
         );
 
@@ -801,10 +2038,9 @@ mod tests {
                 ContainerInfo {
                     id: "test123".to_string(),
                     name: "web".to_string(),
-                    ip_address: None,
                     networks,
-                    domain_names: vec![],
                     running: true,
+                    ..Default::default()
                 },
             );
         }
@@ -838,6 +2074,91 @@ mod tests {
         assert_eq!(content, "127.0.0.1 localhost\n");
     }
 
+    #[tokio::test]
+    async fn test_preview_splices_without_writing() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_path_buf();
+        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), false, 100);
+        seed_container(&sync, "abc123", "web", "172.17.0.2").await;
+
+        let (current, proposed) = sync.preview().await.unwrap();
+        assert_eq!(current, "127.0.0.1 localhost\n");
+        assert!(
+            proposed.contains("172.17.0.2 web.docker"),
+            "the entry is in the preview"
+        );
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            current,
+            "the file is left alone"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_event_stream_not_live_while_docker_is_unreachable() {
+        let dir = tempfile::tempdir().unwrap();
+        // Not a socket, so nothing answers on it
+        let socket = dir.path().join("docker.sock");
+        fs::write(&socket, "").unwrap();
+        let docker =
+            Docker::connect_with_socket(&socket.to_string_lossy(), 5, bollard::API_DEFAULT_VERSION)
+                .unwrap();
+        let sync = Synchronizer::new(
+            docker,
+            PathBuf::from("/etc/hosts"),
+            ".docker".to_string(),
+            false,
+            100,
+        );
+
+        tokio::select! {
+            result = sync.follow_events() => panic!("stopped reconnecting: {result:?}"),
+            () = async {
+                for _ in 0..3 {
+                    sleep(Duration::from_millis(100)).await;
+                    assert!(
+                        sync.health.liveness().is_err(),
+                        "reported live while reconnecting"
+                    );
+                    assert!(
+                        sync.event_loop_healthy(),
+                        "the watchdog must keep being pinged while waiting for Docker"
+                    );
+                }
+            } => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_records_metrics() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_path_buf();
+        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 100);
+        seed_container(&sync, "abc123", "web", "172.17.0.2").await;
+
+        sync.write_hosts_file_immediate().await.unwrap();
+        sync.write_hosts_file_immediate().await.unwrap();
+
+        let metrics = sync.metrics();
+        let load = |value: &std::sync::atomic::AtomicU64| value.load(atomic::Ordering::Relaxed);
+        assert_eq!(load(&metrics.writes), 1, "the second write is skipped");
+        assert_eq!(load(&metrics.skipped_writes), 1);
+        assert_eq!(load(&metrics.active_containers), 1);
+        assert_eq!(load(&metrics.hostnames_published), 1);
+        assert!(
+            metrics
+                .to_string()
+                .contains("docker_hostmanager_write_duration_seconds_count 1\n"),
+            "the write is timed"
+        );
+    }
+
     #[tokio::test]
     async fn test_write_hosts_file_removes_empty_section() {
         let temp_file = NamedTempFile::new().unwrap();
@@ -886,6 +2207,7 @@ mod tests {
             NetworkInfo {
                 ip_address: "172.18.0.2".to_string(),
                 aliases: vec!["web".to_string()],
+                ..Default::default()
             },
         );
 
@@ -896,10 +2218,9 @@ mod tests {
                 ContainerInfo {
                     id: "test123".to_string(),
                     name: "web".to_string(),
-                    ip_address: None,
                     networks,
-                    domain_names: vec![],
                     running: true,
+                    ..Default::default()
                 },
             );
         }
@@ -945,7 +2266,7 @@ mod tests {
             ..Default::default()
         };
 
-        let info = Synchronizer::extract_container_info(container);
+        let info = Synchronizer::extract_container_info(container, &NetworkFilter::default());
         assert!(info.is_some());
 
         let container_info = info.unwrap();
@@ -985,7 +2306,7 @@ mod tests {
             ..Default::default()
         };
 
-        let info = Synchronizer::extract_container_info(container);
+        let info = Synchronizer::extract_container_info(container, &NetworkFilter::default());
         assert!(info.is_some());
 
         let container_info = info.unwrap();
@@ -1025,7 +2346,7 @@ mod tests {
             ..Default::default()
         };
 
-        let info = Synchronizer::extract_container_info(container);
+        let info = Synchronizer::extract_container_info(container, &NetworkFilter::default());
         assert!(info.is_some());
 
         let container_info = info.unwrap();
@@ -1061,7 +2382,7 @@ mod tests {
             ..Default::default()
         };
 
-        let info = Synchronizer::extract_container_info(container);
+        let info = Synchronizer::extract_container_info(container, &NetworkFilter::default());
         assert!(info.is_some());
         let container_info = info.unwrap();
 
@@ -1104,7 +2425,7 @@ mod tests {
             ..Default::default()
         };
 
-        let info = Synchronizer::extract_container_info(container);
+        let info = Synchronizer::extract_container_info(container, &NetworkFilter::default());
         assert!(info.is_some());
         let container_info = info.unwrap();
         assert!(container_info
@@ -1134,7 +2455,7 @@ mod tests {
             ..Default::default()
         };
 
-        let info = Synchronizer::extract_container_info(container);
+        let info = Synchronizer::extract_container_info(container, &NetworkFilter::default());
         assert!(info.is_some());
         let container_info = info.unwrap();
         // Fallback should extract "myworktree" from "/myworktree-web-1"
@@ -1143,58 +2464,386 @@ mod tests {
             .contains(&"myworktree.example.com".to_string()));
     }
 
-    // ── debounce behaviour ────────────────────────────────────────────
+    #[test]
+    fn test_extract_container_info_with_network_template() {
+        let mut labels = HashMap::new();
+        labels.insert("com.docker.compose.service".to_string(), "api".to_string());
+        labels.insert(
+            "dev.orbstack.domains".to_string(),
+            "{label:missing".to_string(),
+        );
 
-    /// Helper: insert a single container with the given name and IP into
-    /// `sync.active_containers`.
-    async fn seed_container(sync: &Synchronizer, id: &str, name: &str, ip: &str) {
-        let mut active = sync.active_containers.lock().await;
-        active.insert(
-            id.to_string(),
-            ContainerInfo {
-                id: id.to_string(),
-                name: name.to_string(),
-                ip_address: Some(ip.to_string()),
-                networks: HashMap::new(),
-                domain_names: vec![],
-                running: true,
+        let mut networks = HashMap::new();
+        networks.insert(
+            "backend".to_string(),
+            bollard::models::EndpointSettings {
+                ip_address: Some("172.18.0.2".to_string()),
+                ..Default::default()
             },
         );
-    }
 
-    #[tokio::test]
-    async fn test_debounce_delays_write() {
-        let temp_file = NamedTempFile::new().unwrap();
-        let path = temp_file.path().to_path_buf();
-        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+        let container = ContainerInspectResponse {
+            id: Some("nettmpl123".to_string()),
+            name: Some("/myproject-api-1".to_string()),
+            state: Some(bollard::models::ContainerState {
+                running: Some(true),
+                ..Default::default()
+            }),
+            config: Some(bollard::models::ContainerConfig {
+                env: Some(vec![
+                    "DOMAIN_NAME={service}.{network|upper|lower}.local".to_string()
+                ]),
+                labels: Some(labels),
+                ..Default::default()
+            }),
+            network_settings: Some(bollard::models::NetworkSettings {
+                networks: Some(networks),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
 
-        let docker = Docker::connect_with_socket_defaults().unwrap();
-        // 100 ms debounce window
-        let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 100);
-        seed_container(&sync, "c1", "nginx", "172.17.0.2").await;
+        let container_info =
+            Synchronizer::extract_container_info(container, &NetworkFilter::default()).unwrap();
+        // {network} templates are bound to the network they were rendered for,
+        // and the malformed label template is dropped entirely
+        assert_eq!(
+            container_info.domain_names,
+            vec!["backend:api.backend.local".to_string()]
+        );
+    }
 
-        tokio::select! {
-            result = sync.process_pending_writes() => { result.unwrap(); }
-            () = async {
-                sync.schedule_write();
+    #[test]
+    fn test_extract_container_info_with_native_labels() {
+        let labels = HashMap::from([
+            (DOMAINS_LABEL.to_string(), "api.local".to_string()),
+            (ALIASES_LABEL.to_string(), "backend".to_string()),
+            (NETWORKS_LABEL.to_string(), "default".to_string()),
+            (
+                EXCLUDE_NAMES_LABEL.to_string(),
+                "shop-api-1.shop_default".to_string(),
+            ),
+            (TLD_LABEL.to_string(), ".test".to_string()),
+        ]);
 
-                // 30 ms in — well before the 100 ms window; file untouched
-                sleep(Duration::from_millis(30)).await;
-                let content = fs::read_to_string(&path).unwrap();
-                assert!(
-                    !content.contains(START_TAG),
-                    "file written before debounce window expired"
-                );
+        let endpoint = |ip: &str| bollard::models::EndpointSettings {
+            ip_address: Some(ip.to_string()),
+            ..Default::default()
+        };
+        let networks = HashMap::from([
+            ("shop_default".to_string(), endpoint("172.18.0.2")),
+            ("internal".to_string(), endpoint("172.19.0.2")),
+        ]);
 
-                // 150 ms in — well after the window; file written
-                sleep(Duration::from_millis(120)).await;
-                let content = fs::read_to_string(&path).unwrap();
-                assert!(
-                    content.contains("172.17.0.2 nginx.docker"),
-                    "file not written after debounce window"
-                );
-            } => {}
+        let container = ContainerInspectResponse {
+            id: Some("labels123".to_string()),
+            name: Some("/shop-api-1".to_string()),
+            state: Some(bollard::models::ContainerState {
+                running: Some(true),
+                ..Default::default()
+            }),
+            config: Some(bollard::models::ContainerConfig {
+                env: Some(vec!["DOMAIN_NAME=legacy.local".to_string()]),
+                labels: Some(labels),
+                ..Default::default()
+            }),
+            network_settings: Some(bollard::models::NetworkSettings {
+                networks: Some(networks),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let info =
+            Synchronizer::extract_container_info(container, &NetworkFilter::default()).unwrap();
+        assert_eq!(
+            info.domain_names,
+            vec!["api.local".to_string()],
+            "the domains label replaces DOMAIN_NAME"
+        );
+        assert_eq!(info.tld.as_deref(), Some(".test"));
+        assert_eq!(
+            info.networks.keys().collect::<Vec<_>>(),
+            vec!["shop_default"],
+            "only the labelled network is published"
+        );
+        assert_eq!(
+            info.get_hostnames(".docker"),
+            vec![(
+                "172.18.0.2".to_string(),
+                vec![
+                    "shop-api-1.test".to_string(),
+                    "backend.shop_default".to_string(),
+                    "api.local".to_string()
+                ]
+            )],
+            "the TLD label publishes the bare name, extra alias is added \
+             and the excluded name is dropped"
+        );
+    }
+
+    #[test]
+    fn test_extract_container_info_with_network_filter() {
+        let endpoint = |ip: &str| bollard::models::EndpointSettings {
+            ip_address: Some(ip.to_string()),
+            ..Default::default()
+        };
+        let networks = HashMap::from([
+            ("shop_default".to_string(), endpoint("172.18.0.2")),
+            ("shop_internal".to_string(), endpoint("172.19.0.2")),
+            ("public".to_string(), endpoint("172.21.0.2")),
+        ]);
+        let labels = HashMap::from([(NETWORKS_LABEL.to_string(), "shop_*".to_string())]);
+
+        let container = ContainerInspectResponse {
+            id: Some("filter123".to_string()),
+            name: Some("/shop-web-1".to_string()),
+            state: Some(bollard::models::ContainerState {
+                running: Some(true),
+                ..Default::default()
+            }),
+            config: Some(bollard::models::ContainerConfig {
+                labels: Some(labels),
+                ..Default::default()
+            }),
+            network_settings: Some(bollard::models::NetworkSettings {
+                networks: Some(networks),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        // The label keeps shop_* networks, the global filter then drops *_internal
+        let filter = NetworkFilter::new(vec![], vec!["*_internal".to_string()]);
+        let info = Synchronizer::extract_container_info(container, &filter).unwrap();
+        assert_eq!(
+            info.networks.keys().collect::<Vec<_>>(),
+            vec!["shop_default"]
+        );
+    }
+
+    // ── debounce behaviour ────────────────────────────────────────────
+
+    /// Helper: insert a single container with the given name and IP into
+    /// `sync.active_containers`.
+    async fn seed_container(sync: &Synchronizer, id: &str, name: &str, ip: &str) {
+        let mut active = sync.active_containers.lock().await;
+        active.insert(
+            id.to_string(),
+            ContainerInfo {
+                id: id.to_string(),
+                name: name.to_string(),
+                ip_address: Some(ip.to_string()),
+                running: true,
+                ..Default::default()
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reclaiming_counts_conflicts_once() {
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(
+            docker,
+            PathBuf::from("/etc/hosts"),
+            ".docker".to_string(),
+            false,
+            100,
+        );
+        let web = |id: &str, ip: &str| ContainerInfo {
+            id: id.to_string(),
+            name: "web".to_string(),
+            ip_address: Some(ip.to_string()),
+            running: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            sync.claim_hostnames("aaa", &web("aaa", "172.17.0.2")).await,
+            vec!["web.docker".to_string()]
+        );
+        assert_eq!(
+            sync.claim_hostnames("aaa", &web("aaa", "172.17.0.2")).await,
+            vec!["web.docker".to_string()],
+            "claiming its own hostname again, e.g. for a replayed event, is no conflict"
+        );
+        assert!(sync
+            .claim_hostnames("bbb", &web("bbb", "172.17.0.3"))
+            .await
+            .is_empty());
+        assert_eq!(sync.metrics.conflicts.load(atomic::Ordering::Relaxed), 1);
+
+        // Rebuilding the claims, as reloading does, with the conflict known
+        let mut claims = HashMap::new();
+        let mut wildcard_claims = HashSet::new();
+        for id in ["aaa", "bbb"] {
+            sync.claim_in(
+                &mut claims,
+                &mut wildcard_claims,
+                id,
+                &web(id, "172.17.0.2"),
+                |hostname, owner_id| hostname == "web.docker" && owner_id == "aaa",
+            );
+        }
+        assert_eq!(
+            sync.metrics.conflicts.load(atomic::Ordering::Relaxed),
+            1,
+            "a known conflict isn't counted again"
+        );
+    }
+
+    /// Collects what a subscriber writes, for checking log events.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        /// The JSON events logged so far.
+        fn events(&self) -> Vec<serde_json::Value> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status_lines_are_structured_events() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .flatten_event(true)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_path_buf();
+        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(docker, path, ".docker".to_string(), true, 100)
+            .with_rendering(GroupBy::None, true);
+
+        let info = ContainerInfo {
+            id: "0123456789abcdef".to_string(),
+            name: "web".to_string(),
+            ip_address: Some("172.17.0.2".to_string()),
+            running: true,
+            ..Default::default()
+        };
+        sync.add_container("0123456789abcdef", info).await;
+        sync.write_hosts_file_immediate().await.unwrap();
+        sync.handle_container_down("0123456789abcdef").await;
+
+        let events = captured.events();
+        let find = |message: &str| {
+            events
+                .iter()
+                .find(|e| e["message"] == message)
+                .unwrap_or_else(|| panic!("no \"{message}\" event in {events:?}"))
+        };
+        let started = find("Container started");
+        assert_eq!(started["container_id"], "0123456789ab");
+        assert_eq!(started["name"], "web");
+        assert_eq!(started["hostnames"], "web.docker");
+        let stopped = find("Container stopped");
+        assert_eq!(stopped["container_id"], "0123456789ab");
+        assert_eq!(stopped["name"], "web");
+        let updated = find("Updated hosts file");
+        assert_eq!(updated["containers"], 1);
+        assert_eq!(updated["hostnames"], 1);
+        // The section is logged as written, honouring --annotate, with fields
+        let entry = find("172.17.0.2 web.docker  # web (0123456789ab)");
+        assert_eq!(entry["ip"], "172.17.0.2");
+        assert_eq!(entry["hostnames"], "web.docker");
+    }
+
+    #[tokio::test]
+    async fn test_debounce_delays_write() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_path_buf();
+        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        // 100 ms debounce window
+        let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 100);
+        seed_container(&sync, "c1", "nginx", "172.17.0.2").await;
+
+        tokio::select! {
+            result = sync.process_pending_writes() => { result.unwrap(); }
+            () = async {
+                sync.schedule_write();
+
+                // 30 ms in — well before the 100 ms window; file untouched
+                sleep(Duration::from_millis(30)).await;
+                let content = fs::read_to_string(&path).unwrap();
+                assert!(
+                    !content.contains(START_TAG),
+                    "file written before debounce window expired"
+                );
+
+                // 150 ms in — well after the window; file written
+                sleep(Duration::from_millis(120)).await;
+                let content = fs::read_to_string(&path).unwrap();
+                assert!(
+                    content.contains("172.17.0.2 nginx.docker"),
+                    "file not written after debounce window"
+                );
+            } => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_is_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        // A missing hosts file makes the write fail
+        let path = dir.path().join("hosts");
+
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 10);
+        seed_container(&sync, "c1", "nginx", "172.17.0.2").await;
+        sync.health.set_synchronized();
+
+        tokio::select! {
+            result = sync.process_pending_writes() => { result.unwrap(); }
+            () = async {
+                sync.schedule_write();
+                sleep(Duration::from_millis(100)).await;
+                assert!(!path.exists());
+                assert!(sync.health.readiness().is_err());
+
+                // Fixed without any further event
+                fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+                while !fs::read_to_string(&path).unwrap().contains(START_TAG) {
+                    sleep(Duration::from_millis(20)).await;
+                }
+            } => {}
+            () = sleep(Duration::from_secs(5)) => panic!("the failed write was not retried"),
         }
+        assert_eq!(
+            sync.metrics
+                .debounced_writes
+                .load(atomic::Ordering::Relaxed),
+            1,
+            "only the successful write is counted"
+        );
+        assert_eq!(
+            sync.metrics.write_requests.load(atomic::Ordering::Relaxed),
+            1,
+            "retries aren't write requests"
+        );
     }
 
     #[tokio::test]
@@ -1312,9 +2961,9 @@ mod tests {
             id: "aaa".to_string(),
             name: "container-a".to_string(),
             ip_address: Some("172.17.0.2".to_string()),
-            networks: HashMap::new(),
             domain_names: vec!["myapp.local".to_string()],
             running: true,
+            ..Default::default()
         };
         seed_container_claimed(&sync, "aaa", container_a).await;
 
@@ -1323,9 +2972,9 @@ mod tests {
             id: "bbb".to_string(),
             name: "container-b".to_string(),
             ip_address: Some("172.17.0.3".to_string()),
-            networks: HashMap::new(),
             domain_names: vec!["myapp.local".to_string()],
             running: true,
+            ..Default::default()
         };
         seed_container_claimed(&sync, "bbb", container_b).await;
 
@@ -1374,17 +3023,17 @@ mod tests {
             id: "aaa".to_string(),
             name: "container-a".to_string(),
             ip_address: Some("172.17.0.2".to_string()),
-            networks: HashMap::new(),
             domain_names: vec!["shared.local".to_string()],
             running: true,
+            ..Default::default()
         };
         let container_b = ContainerInfo {
             id: "bbb".to_string(),
             name: "container-b".to_string(),
             ip_address: Some("172.17.0.3".to_string()),
-            networks: HashMap::new(),
             domain_names: vec!["shared.local".to_string()],
             running: true,
+            ..Default::default()
         };
 
         // B claims first (even though A sorts alphabetically earlier)
@@ -1428,9 +3077,9 @@ mod tests {
             id: "aaa".to_string(),
             name: "container-a".to_string(),
             ip_address: Some("172.17.0.2".to_string()),
-            networks: HashMap::new(),
             domain_names: vec!["myapp.local".to_string()],
             running: true,
+            ..Default::default()
         };
         seed_container_claimed(&sync, "aaa", container_a.clone()).await;
 
@@ -1446,9 +3095,9 @@ mod tests {
             id: "bbb".to_string(),
             name: "container-b".to_string(),
             ip_address: Some("172.17.0.3".to_string()),
-            networks: HashMap::new(),
             domain_names: vec!["myapp.local".to_string()],
             running: true,
+            ..Default::default()
         };
         seed_container_claimed(&sync, "bbb", container_b).await;
 
@@ -1488,6 +3137,7 @@ mod tests {
             NetworkInfo {
                 ip_address: "172.18.0.2".to_string(),
                 aliases: vec!["web".to_string()],
+                ..Default::default()
             },
         );
         let mut networks_b = HashMap::new();
@@ -1496,6 +3146,7 @@ mod tests {
             NetworkInfo {
                 ip_address: "172.19.0.2".to_string(),
                 aliases: vec!["web".to_string()],
+                ..Default::default()
             },
         );
 
@@ -1505,10 +3156,9 @@ mod tests {
             ContainerInfo {
                 id: "aaa".to_string(),
                 name: "web-a".to_string(),
-                ip_address: None,
                 networks: networks_a,
-                domain_names: vec![],
                 running: true,
+                ..Default::default()
             },
         )
         .await;
@@ -1518,10 +3168,9 @@ mod tests {
             ContainerInfo {
                 id: "bbb".to_string(),
                 name: "web-b".to_string(),
-                ip_address: None,
                 networks: networks_b,
-                domain_names: vec![],
                 running: true,
+                ..Default::default()
             },
         )
         .await;
@@ -1556,9 +3205,9 @@ mod tests {
                 id: "aaa".to_string(),
                 name: "clash-app".to_string(),
                 ip_address: Some("172.17.0.2".to_string()),
-                networks: HashMap::new(),
                 domain_names: vec!["clash.local".to_string()],
                 running: true,
+                ..Default::default()
             },
         )
         .await;
@@ -1571,9 +3220,9 @@ mod tests {
                 id: "bbb".to_string(),
                 name: "clash-app".to_string(),
                 ip_address: Some("172.17.0.99".to_string()),
-                networks: HashMap::new(),
                 domain_names: vec!["clash.local".to_string()],
                 running: true,
+                ..Default::default()
             },
         )
         .await;
@@ -1615,12 +3264,12 @@ mod tests {
                 id: "ccc".to_string(),
                 name: "app".to_string(),
                 ip_address: Some("172.18.0.5".to_string()),
-                networks: HashMap::new(),
                 domain_names: vec![
                     "{APP_SECRET_FILE}.app2.local".to_string(),
                     "valid.local".to_string(),
                 ],
                 running: true,
+                ..Default::default()
             },
         )
         .await;
@@ -1655,9 +3304,8 @@ mod tests {
                 id: "ccc".to_string(),
                 name: "c-app".to_string(),
                 ip_address: Some("172.17.0.2".to_string()),
-                networks: HashMap::new(),
-                domain_names: vec![],
                 running: true,
+                ..Default::default()
             },
         )
         .await;
@@ -1669,9 +3317,8 @@ mod tests {
                 id: "aaa".to_string(),
                 name: "a-app".to_string(),
                 ip_address: Some("10.0.0.2".to_string()),
-                networks: HashMap::new(),
-                domain_names: vec![],
                 running: true,
+                ..Default::default()
             },
         )
         .await;
@@ -1683,9 +3330,8 @@ mod tests {
                 id: "bbb".to_string(),
                 name: "b-app".to_string(),
                 ip_address: Some("10.0.0.1".to_string()),
-                networks: HashMap::new(),
-                domain_names: vec![],
                 running: true,
+                ..Default::default()
             },
         )
         .await;
@@ -1723,69 +3369,346 @@ mod tests {
         );
     }
 
-    // ── partial release on network disconnect ─────────────────────────────
+    #[tokio::test]
+    async fn test_write_hosts_file_gateway_entries() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_path_buf();
+        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 100)
+            .with_gateway_entries(Some("host.docker.internal".to_string()));
 
-    /// Builds a `ContainerInfo` resembling the urq dev setup: a `web` container
-    /// attached to a project-private `*_default` network (where it owns the
-    /// project's local dev domain via a `default:hostname` env entry) and to a
-    /// shared `public` network (used by an external proxy / tunnel).
-    fn multi_network_web(
-        id: &str,
-        name: &str,
-        default_net: &str,
-        default_ip: &str,
-        public_ip: &str,
-        dev_domain: &str,
-    ) -> ContainerInfo {
         let mut networks = HashMap::new();
         networks.insert(
-            default_net.to_string(),
+            "myapp".to_string(),
             NetworkInfo {
-                ip_address: default_ip.to_string(),
-                aliases: vec!["web".to_string()],
+                ip_address: "172.18.0.2".to_string(),
+                gateway: Some("172.18.0.1".to_string()),
+                ..Default::default()
             },
         );
         networks.insert(
             "public".to_string(),
             NetworkInfo {
-                ip_address: public_ip.to_string(),
-                aliases: vec!["web".to_string()],
+                ip_address: "172.21.0.2".to_string(),
+                gateway: Some("172.21.0.1".to_string()),
+                ..Default::default()
             },
         );
-        ContainerInfo {
-            id: id.to_string(),
-            name: name.to_string(),
-            ip_address: None,
-            networks,
-            domain_names: vec![format!("default:{dev_domain}")],
-            running: true,
-        }
+        seed_container_claimed(
+            &sync,
+            "aaa",
+            ContainerInfo {
+                id: "aaa".to_string(),
+                name: "web".to_string(),
+                networks,
+                running: true,
+                ..Default::default()
+            },
+        )
+        .await;
+
+        sync.write_hosts_file_immediate().await.unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+
+        assert!(
+            content.contains("172.18.0.1 gateway.myapp host.docker.internal\n"),
+            "first network's gateway should carry the host alias"
+        );
+        assert!(
+            content.contains("172.21.0.1 gateway.public\n"),
+            "every other gateway gets only its gateway.<network> name"
+        );
+        assert!(content.contains("172.18.0.2 web.myapp"));
     }
 
     #[tokio::test]
-    async fn test_release_hostnames_with_network_filter_only_drops_that_networks_claims() {
+    async fn test_exact_hostname_beats_wildcard_expansion() {
         let temp_file = NamedTempFile::new().unwrap();
-        let docker = Docker::connect_with_socket_defaults().unwrap();
-        let sync = Synchronizer::new(
-            docker,
-            temp_file.path().to_path_buf(),
-            ".docker".to_string(),
-            true,
-            100,
-        );
+        let path = temp_file.path().to_path_buf();
+        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
 
-        let main = multi_network_web(
-            "main-id",
-            "urq-web-1",
-            "urq_default",
-            "172.22.0.3",
-            "172.21.0.3",
-            "dkarlovi-dev.urq.app",
-        );
-        seed_container_claimed(&sync, "main-id", main.clone()).await;
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 100);
 
-        // Sanity: every hostname is claimed by main.
-        {
+        // The wildcard container starts first and claims every expansion...
+        seed_container_claimed(
+            &sync,
+            "aaa",
+            ContainerInfo {
+                id: "aaa".to_string(),
+                name: "tenants".to_string(),
+                ip_address: Some("172.17.0.2".to_string()),
+                domain_names: vec!["*.app.test".to_string()],
+                subdomains: vec!["acme".to_string(), "admin".to_string()],
+                running: true,
+                ..Default::default()
+            },
+        )
+        .await;
+        // ...but an exact name still takes over its single expansion.
+        seed_container_claimed(
+            &sync,
+            "bbb",
+            ContainerInfo {
+                id: "bbb".to_string(),
+                name: "admin".to_string(),
+                ip_address: Some("172.17.0.3".to_string()),
+                domain_names: vec!["admin.app.test".to_string()],
+                running: true,
+                ..Default::default()
+            },
+        )
+        .await;
+
+        sync.write_hosts_file_immediate().await.unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+
+        assert!(
+            content.contains("172.17.0.2 tenants.docker acme.app.test  # skipped: admin.app.test"),
+            "wildcard container keeps the expansions nobody claimed exactly"
+        );
+        assert!(
+            content.contains("172.17.0.3 admin.docker admin.app.test\n"),
+            "exact name wins over the wildcard expansion"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unexpanded_wildcard_is_skipped() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_path_buf();
+        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 100);
+
+        seed_container_claimed(
+            &sync,
+            "aaa",
+            ContainerInfo {
+                id: "aaa".to_string(),
+                name: "app".to_string(),
+                ip_address: Some("172.17.0.2".to_string()),
+                domain_names: vec!["*.app.test".to_string()],
+                running: true,
+                ..Default::default()
+            },
+        )
+        .await;
+
+        sync.write_hosts_file_immediate().await.unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+
+        assert!(
+            content.contains("172.17.0.2 app.docker  # skipped: *.app.test"),
+            "a wildcard must never be written literally"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_hosts_file_grouped_by_project_with_annotations() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_path_buf();
+        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 100)
+            .with_rendering(GroupBy::Project, true);
+
+        for (id, name, ip, project) in [
+            ("bbb000000000ffff", "shop-web", "172.17.0.2", Some("shop")),
+            ("aaa000000000ffff", "shop-api", "172.17.0.9", Some("shop")),
+            ("ccc000000000ffff", "blog-web", "172.17.0.5", Some("blog")),
+            ("ddd000000000ffff", "standalone", "172.17.0.1", None),
+        ] {
+            seed_container_claimed(
+                &sync,
+                id,
+                ContainerInfo {
+                    id: id.to_string(),
+                    name: name.to_string(),
+                    ip_address: Some(ip.to_string()),
+                    project: project.map(str::to_string),
+                    running: true,
+                    ..Default::default()
+                },
+            )
+            .await;
+        }
+
+        sync.write_hosts_file_immediate().await.unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+
+        let expected = format!(
+            "{START_TAG}\n\
+             # project: blog\n\
+             172.17.0.5 blog-web.docker  # blog-web (ccc000000000)\n\
+             # project: shop\n\
+             172.17.0.9 shop-api.docker  # shop-api (aaa000000000)\n\
+             172.17.0.2 shop-web.docker  # shop-web (bbb000000000)\n\
+             # project: (none)\n\
+             172.17.0.1 standalone.docker  # standalone (ddd000000000)\n\
+             {END_TAG}\n"
+        );
+        assert!(
+            content.ends_with(&expected),
+            "projects sorted by name with entries sorted by hostname, got:\n{content}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_hosts_file_sorts_ips_numerically() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_path_buf();
+        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 100);
+        seed_container(&sync, "a", "ten", "172.17.0.10").await;
+        seed_container(&sync, "b", "nine", "172.17.0.9").await;
+
+        sync.write_hosts_file_immediate().await.unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+
+        let nine = content.find("172.17.0.9 ").unwrap();
+        let ten = content.find("172.17.0.10 ").unwrap();
+        assert!(nine < ten, ".9 sorts before .10, unlike a string sort");
+    }
+
+    /// Lines sharing an IP used to come out in `HashMap` iteration order, so
+    /// every write could reshuffle them. Each `Synchronizer` gets freshly seeded
+    /// maps, so repeating the write across instances must be byte-identical.
+    #[tokio::test]
+    async fn test_write_hosts_file_output_is_stable() {
+        let mut outputs = Vec::new();
+        for _ in 0..10 {
+            let temp_file = NamedTempFile::new().unwrap();
+            let path = temp_file.path().to_path_buf();
+            fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+            let docker = Docker::connect_with_socket_defaults().unwrap();
+            let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 100)
+                .with_gateway_entries(Some("host.docker.internal".to_string()));
+
+            for (id, name) in [("c1", "charlie"), ("c2", "alpha"), ("c3", "bravo")] {
+                let mut networks = HashMap::new();
+                for (net, ip, gateway) in [
+                    ("shared", "172.20.0.2", "172.20.0.1"),
+                    ("zeta", "172.30.0.2", "172.30.0.1"),
+                ] {
+                    networks.insert(
+                        net.to_string(),
+                        NetworkInfo {
+                            ip_address: ip.to_string(),
+                            aliases: vec![format!("{name}-alias"), "common".to_string()],
+                            gateway: Some(gateway.to_string()),
+                        },
+                    );
+                }
+                seed_container_claimed(
+                    &sync,
+                    id,
+                    ContainerInfo {
+                        id: id.to_string(),
+                        name: name.to_string(),
+                        networks,
+                        domain_names: vec!["example.test".to_string()],
+                        running: true,
+                        ..Default::default()
+                    },
+                )
+                .await;
+            }
+
+            for _ in 0..3 {
+                sync.write_hosts_file_immediate().await.unwrap();
+                outputs.push(fs::read_to_string(&path).unwrap());
+            }
+        }
+
+        assert!(
+            outputs.iter().all(|o| *o == outputs[0]),
+            "output changed between writes:\n{}\n---\n{}",
+            outputs[0],
+            outputs
+                .iter()
+                .find(|o| **o != outputs[0])
+                .unwrap_or(&outputs[0])
+        );
+        // Same IP for every container, so the container name decides the order
+        let alpha = outputs[0].find("172.20.0.2 alpha.shared").unwrap();
+        let bravo = outputs[0].find("172.20.0.2 bravo.shared").unwrap();
+        let charlie = outputs[0].find("172.20.0.2 charlie.shared").unwrap();
+        assert!(alpha < bravo && bravo < charlie);
+    }
+
+    // ── partial release on network disconnect ─────────────────────────────
+
+    /// Builds a `ContainerInfo` resembling the urq dev setup: a `web` container
+    /// attached to a project-private `*_default` network (where it owns the
+    /// project's local dev domain via a `default:hostname` env entry) and to a
+    /// shared `public` network (used by an external proxy / tunnel).
+    fn multi_network_web(
+        id: &str,
+        name: &str,
+        default_net: &str,
+        default_ip: &str,
+        public_ip: &str,
+        dev_domain: &str,
+    ) -> ContainerInfo {
+        let mut networks = HashMap::new();
+        networks.insert(
+            default_net.to_string(),
+            NetworkInfo {
+                ip_address: default_ip.to_string(),
+                aliases: vec!["web".to_string()],
+                ..Default::default()
+            },
+        );
+        networks.insert(
+            "public".to_string(),
+            NetworkInfo {
+                ip_address: public_ip.to_string(),
+                aliases: vec!["web".to_string()],
+                ..Default::default()
+            },
+        );
+        ContainerInfo {
+            id: id.to_string(),
+            name: name.to_string(),
+            networks,
+            domain_names: vec![format!("default:{dev_domain}")],
+            running: true,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_release_hostnames_with_network_filter_only_drops_that_networks_claims() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(
+            docker,
+            temp_file.path().to_path_buf(),
+            ".docker".to_string(),
+            true,
+            100,
+        );
+
+        let main = multi_network_web(
+            "main-id",
+            "urq-web-1",
+            "urq_default",
+            "172.22.0.3",
+            "172.21.0.3",
+            "dkarlovi-dev.urq.app",
+        );
+        seed_container_claimed(&sync, "main-id", main.clone()).await;
+
+        // Sanity: every hostname is claimed by main.
+        {
             let claims: HashMap<String, (String, String)> =
                 sync.hostname_claims.lock().await.clone();
             for h in ["dkarlovi-dev.urq.app", "web.urq_default", "web.public"] {
@@ -1908,6 +3831,47 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_network_disconnect_from_filtered_network_keeps_claims() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(
+            docker,
+            temp_file.path().to_path_buf(),
+            ".docker".to_string(),
+            true,
+            100,
+        )
+        .with_network_filter(NetworkFilter::new(vec![], vec!["*_internal".to_string()]));
+
+        // Extracted through the filter, so urq_internal isn't in its networks
+        let main = multi_network_web(
+            "main-id",
+            "urq-web-1",
+            "urq_default",
+            "172.22.0.3",
+            "172.21.0.3",
+            "dkarlovi-dev.urq.app",
+        );
+        seed_container_claimed(&sync, "main-id", main).await;
+
+        sync.handle_network_disconnect("main-id", "urq_internal")
+            .await;
+
+        let claims: HashMap<String, (String, String)> = sync.hostname_claims.lock().await.clone();
+        for h in ["dkarlovi-dev.urq.app", "web.urq_default", "web.public"] {
+            assert_eq!(
+                claims.get(h).map(|(id, _)| id.as_str()),
+                Some("main-id"),
+                "{h} stays with main after leaving a filtered-out network"
+            );
+        }
+        assert!(
+            sync.active_containers.lock().await.contains_key("main-id"),
+            "main stays active"
+        );
+    }
+
     #[tokio::test]
     async fn test_network_event_targets_extracts_container_and_network_from_attributes() {
         let mut attrs = HashMap::new();
@@ -1933,4 +3897,531 @@ mod tests {
         );
         assert_eq!(Synchronizer::network_event_targets(None), None);
     }
+
+    #[tokio::test]
+    async fn test_unchanged_content_skips_write() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_path_buf();
+        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 100);
+        seed_container_claimed(
+            &sync,
+            "aaa",
+            ContainerInfo {
+                id: "aaa".to_string(),
+                name: "web".to_string(),
+                ip_address: Some("172.17.0.2".to_string()),
+                running: true,
+                ..Default::default()
+            },
+        )
+        .await;
+
+        sync.write_hosts_file_immediate().await.unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            sync.metrics.skipped_writes.load(atomic::Ordering::Relaxed),
+            0
+        );
+
+        // Make any rewrite observable even on filesystems with coarse mtimes
+        let old_mtime = std::time::SystemTime::UNIX_EPOCH;
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(old_mtime)
+            .unwrap();
+
+        sync.write_hosts_file_immediate().await.unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), written);
+        assert_eq!(
+            sync.metrics.skipped_writes.load(atomic::Ordering::Relaxed),
+            1
+        );
+        assert_eq!(
+            fs::metadata(&path).unwrap().modified().unwrap(),
+            old_mtime,
+            "an identical render must not touch the file"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_restores_externally_edited_section() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_path_buf();
+        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 100);
+        seed_container(&sync, "aaa", "web", "172.17.0.2").await;
+        sync.write_hosts_file_immediate().await.unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+
+        // Edits outside the managed section are left alone
+        let outside = format!("{written}10.0.0.1 nas\n");
+        fs::write(&path, &outside).unwrap();
+        sync.reconcile_hosts_file().await.unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), outside);
+
+        // Edits inside it, or to its markers, are reverted
+        fs::write(&path, outside.replace("172.17.0.2", "172.17.0.99")).unwrap();
+        sync.reconcile_hosts_file().await.unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), outside);
+
+        fs::write(&path, outside.replace(START_TAG, "")).unwrap();
+        sync.reconcile_hosts_file().await.unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            content.matches(END_TAG).count(),
+            1,
+            "the orphaned end marker must not be duplicated"
+        );
+        assert!(content.contains(START_TAG));
+        assert!(content.contains("10.0.0.1 nas"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_leaves_expected_section_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        // A missing hosts file makes the write fail
+        let path = dir.path().join("hosts");
+
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 100);
+        seed_container(&sync, "aaa", "web", "172.17.0.2").await;
+        assert!(sync.write_hosts_file_immediate().await.is_err());
+
+        assert_eq!(
+            *sync.last_section.lock().await,
+            None,
+            "the unwritten section must not be expected in the file"
+        );
+        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+        sync.reconcile_hosts_file().await.unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "127.0.0.1 localhost\n");
+    }
+
+    #[tokio::test]
+    async fn test_failed_backup_does_not_block_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hosts");
+        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+        // A file where the backup directory should be makes the backup fail
+        let not_a_dir = dir.path().join("backups");
+        fs::write(&not_a_dir, "").unwrap();
+
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 100)
+            .with_backups(
+                Backups::new(&path, Some(not_a_dir), 5),
+                BackupWhen::EveryWrite,
+            );
+        seed_container(&sync, "aaa", "web", "172.17.0.2").await;
+        sync.write_hosts_file_immediate().await.unwrap();
+
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .contains("172.17.0.2 web.docker"));
+    }
+
+    #[tokio::test]
+    async fn test_watch_hosts_file_restores_section_on_edit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hosts");
+        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 100);
+        seed_container(&sync, "aaa", "web", "172.17.0.2").await;
+        sync.write_hosts_file_immediate().await.unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+
+        let edit_and_wait = async {
+            // Give the watcher a moment to start
+            sleep(Duration::from_millis(200)).await;
+            fs::write(&path, written.replace("172.17.0.2", "172.17.0.99")).unwrap();
+            while fs::read_to_string(&path).unwrap() != written {
+                sleep(Duration::from_millis(20)).await;
+            }
+        };
+        tokio::select! {
+            result = sync.watch_hosts_file() => panic!("watcher stopped: {result:?}"),
+            () = edit_and_wait => {}
+            () = sleep(Duration::from_secs(5)) => panic!("section was not restored"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_instances_only_touch_their_own_section() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_path_buf();
+        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let shop = Synchronizer::new(
+            docker.clone(),
+            path.clone(),
+            ".docker".to_string(),
+            true,
+            100,
+        )
+        .with_instance("shop");
+        let blog = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 100)
+            .with_instance("blog");
+        seed_container(&shop, "aaa", "shop-web", "172.17.0.2").await;
+        seed_container(&blog, "bbb", "blog-web", "172.18.0.2").await;
+
+        shop.write_hosts_file_immediate().await.unwrap();
+        blog.write_hosts_file_immediate().await.unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains(&format!("{START_TAG}:shop\n172.17.0.2 shop-web.docker\n")));
+        assert!(content.contains(&format!("{START_TAG}:blog\n172.18.0.2 blog-web.docker\n")));
+
+        // Emptying one instance removes only its section
+        shop.active_containers.lock().await.clear();
+        shop.write_hosts_file_immediate().await.unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(!content.contains(":shop"));
+        assert!(content.contains("172.18.0.2 blog-web.docker"));
+    }
+
+    #[tokio::test]
+    async fn test_flush_writes_pending_changes_immediately() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_path_buf();
+        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 60_000);
+
+        sync.flush().await.unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "127.0.0.1 localhost\n",
+            "nothing to flush without a scheduled write"
+        );
+
+        seed_container(&sync, "aaa", "web", "172.17.0.2").await;
+        sync.schedule_write();
+        sync.flush().await.unwrap();
+        assert!(
+            fs::read_to_string(&path)
+                .unwrap()
+                .contains("172.17.0.2 web.docker"),
+            "the pending write must not wait for the debounce"
+        );
+        assert!(!sync.write_pending.load(atomic::Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_backup_before_first_write_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hosts");
+        fs::write(&path, "127.0.0.1 localhost\n").unwrap();
+        let backups = Backups::new(&path, Some(dir.path().join("backups")), 5);
+        fs::create_dir(dir.path().join("backups")).unwrap();
+
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 100)
+            .with_backups(backups.clone(), BackupWhen::FirstWrite);
+        seed_container(&sync, "aaa", "web", "172.17.0.2").await;
+        sync.write_hosts_file_immediate().await.unwrap();
+        seed_container(&sync, "bbb", "api", "172.17.0.3").await;
+        sync.write_hosts_file_immediate().await.unwrap();
+
+        let list = backups.list().unwrap();
+        assert_eq!(
+            list.len(),
+            1,
+            "only the first write of a session is backed up"
+        );
+        assert_eq!(
+            fs::read_to_string(&list[0]).unwrap(),
+            "127.0.0.1 localhost\n",
+            "the backup holds the file as it was before any change"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_hosts_file_keeps_windows_formatting() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let path = temp_file.path().to_path_buf();
+        fs::write(
+            &path,
+            "\u{feff}# Copyright (c) Microsoft Corp.\r\n127.0.0.1 localhost",
+        )
+        .unwrap();
+
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(docker, path.clone(), ".docker".to_string(), true, 100);
+        seed_container(&sync, "aaa", "web", "172.17.0.2").await;
+        sync.write_hosts_file_immediate().await.unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            content,
+            format!(
+                "\u{feff}# Copyright (c) Microsoft Corp.\r\n127.0.0.1 localhost\r\n\r\n\
+                 {START_TAG}\r\n172.17.0.2 web.docker\r\n{END_TAG}"
+            )
+        );
+
+        sync.write_hosts_file_immediate().await.unwrap();
+        assert_eq!(
+            sync.metrics.skipped_writes.load(atomic::Ordering::Relaxed),
+            1,
+            "the kept formatting must not look like a change"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_owned_conflicting_and_rejected_hostnames() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(
+            docker,
+            temp_file.path().to_path_buf(),
+            ".docker".to_string(),
+            false,
+            100,
+        );
+        let container = |id: &str, ip: &str, domains: &[&str]| ContainerInfo {
+            id: id.to_string(),
+            name: format!("web-{id}"),
+            networks: HashMap::from([(
+                "shop".to_string(),
+                NetworkInfo {
+                    ip_address: ip.to_string(),
+                    ..Default::default()
+                },
+            )]),
+            domain_names: domains.iter().map(|d| (*d).to_string()).collect(),
+            running: true,
+            ..Default::default()
+        };
+        seed_container_claimed(
+            &sync,
+            "aaa",
+            container("aaa", "172.18.0.2", &["shop.local"]),
+        )
+        .await;
+        seed_container_claimed(
+            &sync,
+            "bbb",
+            container("bbb", "172.18.0.3", &["shop.local", "{MISSING}.local"]),
+        )
+        .await;
+
+        let statuses = sync.status().await;
+        assert_eq!(statuses.len(), 2);
+        let (first, second) = (&statuses[0], &statuses[1]);
+        assert_eq!(first.name, "web-aaa");
+        assert_eq!(
+            first.networks,
+            vec![("shop".to_string(), "172.18.0.2".to_string())]
+        );
+        assert!(first
+            .hostnames
+            .contains(&("172.18.0.2".to_string(), "shop.local".to_string())));
+        assert!(first.skipped.is_empty());
+
+        assert!(second.skipped.contains(&(
+            "172.18.0.3".to_string(),
+            "shop.local".to_string(),
+            Skip::Conflict {
+                owner_id: "aaa".to_string(),
+                owner_name: "web-aaa".to_string()
+            }
+        )));
+        assert!(second.skipped.contains(&(
+            "172.18.0.3".to_string(),
+            "{MISSING}.local".to_string(),
+            Skip::UnresolvedVariable
+        )));
+        assert!(second
+            .hostnames
+            .contains(&("172.18.0.3".to_string(), "web-bbb.shop".to_string())));
+    }
+
+    #[test]
+    fn test_explain_reports_containers_without_exposed_ports() {
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(
+            docker,
+            PathBuf::from("/etc/hosts"),
+            ".docker".to_string(),
+            false,
+            100,
+        );
+        // `network_mode: host`, without an IP of its own
+        let container = ContainerInspectResponse {
+            id: Some("aa".to_string()),
+            name: Some("/web".to_string()),
+            state: Some(bollard::models::ContainerState {
+                running: Some(true),
+                ..Default::default()
+            }),
+            config: Some(bollard::models::ContainerConfig {
+                labels: Some(HashMap::from([(
+                    DOMAINS_LABEL.to_string(),
+                    "web.local".to_string(),
+                )])),
+                ..Default::default()
+            }),
+            network_settings: Some(bollard::models::NetworkSettings {
+                networks: Some(HashMap::from([(
+                    "host".to_string(),
+                    bollard::models::EndpointSettings {
+                        ip_address: Some(String::new()),
+                        ..Default::default()
+                    },
+                )])),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let candidates = sync.explain(&container, "web.local", &HashMap::new());
+        assert_eq!(candidates.len(), 1, "{candidates:?}");
+        assert_eq!(candidates[0].verdict, Verdict::NotExposed);
+        assert_eq!(candidates[0].ip, None);
+        assert_eq!(
+            candidates[0].rules,
+            vec![Rule::Domain {
+                source: DOMAINS_LABEL,
+                raw: "web.local".to_string()
+            }]
+        );
+
+        assert!(
+            sync.explain(&container, "web.docker", &HashMap::new())
+                .is_empty(),
+            "without an IP, the container name isn't published under the TLD"
+        );
+    }
+
+    #[test]
+    fn test_explain_reports_rules_and_verdicts() {
+        let docker = Docker::connect_with_socket_defaults().unwrap();
+        let sync = Synchronizer::new(
+            docker,
+            PathBuf::from("/etc/hosts"),
+            ".docker".to_string(),
+            false,
+            100,
+        )
+        .with_container_filter(ContainerFilter::new(vec![(
+            "label".to_string(),
+            "com.docker.compose.project=myapp".to_string(),
+        )]));
+        let container = |id: &str, project: &str, env: &str| ContainerInspectResponse {
+            id: Some(id.to_string()),
+            name: Some(format!("/{project}-api-1")),
+            state: Some(bollard::models::ContainerState {
+                running: Some(true),
+                ..Default::default()
+            }),
+            config: Some(bollard::models::ContainerConfig {
+                env: Some(vec![env.to_string()]),
+                labels: Some(HashMap::from([
+                    (
+                        "com.docker.compose.project".to_string(),
+                        project.to_string(),
+                    ),
+                    ("com.docker.compose.service".to_string(), "api".to_string()),
+                ])),
+                ..Default::default()
+            }),
+            network_settings: Some(bollard::models::NetworkSettings {
+                networks: Some(HashMap::from([(
+                    "myapp".to_string(),
+                    bollard::models::EndpointSettings {
+                        ip_address: Some(format!("172.18.0.{}", id.len())),
+                        aliases: Some(vec!["api".to_string()]),
+                        ..Default::default()
+                    },
+                )])),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let claims = HashMap::from([(
+            "api.myapp".to_string(),
+            ("aa".to_string(), "myapp-api-1".to_string()),
+        )]);
+
+        // Generated both as a network alias and through a DOMAIN_NAME template
+        let owner = sync.explain(
+            &container("aa", "myapp", "DOMAIN_NAME={service}.myapp"),
+            "api.myapp",
+            &claims,
+        );
+        assert_eq!(owner.len(), 1, "one IP generates it");
+        assert_eq!(owner[0].verdict, Verdict::Owns);
+        assert_eq!(
+            owner[0].rules,
+            vec![
+                Rule::Alias("api".to_string()),
+                Rule::Domain {
+                    source: "DOMAIN_NAME",
+                    raw: "{service}.myapp".to_string()
+                }
+            ]
+        );
+
+        let other = sync.explain(
+            &container("bbb", "myapp", "DOMAIN_NAME=api.myapp"),
+            "api.myapp",
+            &claims,
+        );
+        assert_eq!(
+            other[0].verdict,
+            Verdict::Skipped(Skip::Conflict {
+                owner_id: "aa".to_string(),
+                owner_name: "myapp-api-1".to_string()
+            })
+        );
+
+        // Matched through the unresolved variable in the generated name
+        let unresolved = sync.explain(
+            &container("cccc", "myapp", "DOMAIN_NAME={MISSING}.myapp"),
+            "api.myapp",
+            &claims,
+        );
+        let by_template: Vec<&Candidate> = unresolved
+            .iter()
+            .filter(|c| c.hostname == "{MISSING}.myapp")
+            .collect();
+        assert_eq!(
+            by_template[0].verdict,
+            Verdict::Skipped(Skip::UnresolvedVariable)
+        );
+
+        let filtered = sync.explain(
+            &container("ddddd", "other", "DOMAIN_NAME=api.myapp"),
+            "api.myapp",
+            &claims,
+        );
+        assert_eq!(
+            filtered[0].verdict,
+            Verdict::Filtered("doesn't match --filter")
+        );
+        assert!(
+            sync.explain(&container("eeeeee", "other", "X=1"), "web.myapp", &claims)
+                .is_empty(),
+            "the container doesn't generate the hostname"
+        );
+    }
+
+    #[test]
+    fn test_template_placeholders_as_globs() {
+        assert_eq!(
+            template_placeholders_as_globs("{MISSING}.{OTHER}.local"),
+            "*.*.local"
+        );
+        assert_eq!(template_placeholders_as_globs("a{b.local"), "a{b.local");
+    }
 }
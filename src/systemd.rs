@@ -0,0 +1,208 @@
+//! systemd integration: readiness, status and watchdog notifications for
+//! `Type=notify` services, and the unit file behind `install-service`.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::debug;
+
+/// Where `install-service` points backups, since the unit makes the rest of
+/// the file system read-only. Created by `StateDirectory=`.
+pub const STATE_DIRECTORY: &str = "/var/lib/docker-hostmanager";
+
+/// Sends notifications to the socket systemd passes in `NOTIFY_SOCKET`.
+#[derive(Debug)]
+pub struct Notifier {
+    #[cfg(unix)]
+    socket: std::os::unix::net::UnixDatagram,
+}
+
+impl Notifier {
+    /// Connects to `NOTIFY_SOCKET`, or returns `None` when not started by
+    /// systemd (or not on Unix).
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os("NOTIFY_SOCKET")?;
+        Self::connect(Path::new(&path))
+            .map_err(|e| debug!("Can't connect to NOTIFY_SOCKET {}: {}", path.display(), e))
+            .ok()
+    }
+
+    #[cfg(unix)]
+    fn connect(path: &Path) -> std::io::Result<Self> {
+        use std::os::unix::net::UnixDatagram;
+
+        let socket = UnixDatagram::unbound()?;
+        // A leading `@` stands for Linux's abstract socket namespace
+        #[cfg(target_os = "linux")]
+        if let Some(name) = path.to_str().and_then(|p| p.strip_prefix('@')) {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.connect_addr(&addr)?;
+            return Ok(Self { socket });
+        }
+        socket.connect(path)?;
+        Ok(Self { socket })
+    }
+
+    #[cfg(not(unix))]
+    fn connect(_path: &Path) -> std::io::Result<Self> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    /// Sends `state`, e.g. `READY=1`. Failures are only logged, systemd
+    /// notices a missing readiness or watchdog notification by itself.
+    pub fn notify(&self, state: &str) {
+        #[cfg(unix)]
+        if let Err(e) = self.socket.send(state.as_bytes()) {
+            debug!("Failed to notify systemd: {}", e);
+        }
+        #[cfg(not(unix))]
+        let _ = state;
+    }
+
+    /// How often to ping the watchdog: half the `WatchdogSec=` timeout, or
+    /// `None` if the watchdog isn't enabled for this process.
+    pub fn watchdog_interval() -> Option<Duration> {
+        let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+        if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+            if pid.parse() != Ok(std::process::id()) {
+                return None;
+            }
+        }
+        (usec > 0).then(|| Duration::from_micros(usec / 2))
+    }
+}
+
+/// Renders a hardened unit that runs `exec_start`, which syncs `hosts_file`.
+/// `writable` lists the paths besides it that need to stay writable.
+pub fn unit_file(exec_start: &[String], hosts_file: &Path, writable: &[PathBuf]) -> String {
+    let mut read_write = vec![hosts_file.display().to_string()];
+    read_write.extend(writable.iter().map(|p| p.display().to_string()));
+    let command: Vec<String> = exec_start.iter().map(|arg| quote(arg)).collect();
+
+    format!(
+        "\
+[Unit]
+Description=Docker Host Manager for {hosts_file}
+Documentation=https://github.com/dkarlovi/docker-hostmanager.rs
+After=docker.service
+Wants=docker.service
+
+[Service]
+Type=notify
+NotifyAccess=main
+ExecStart={exec_start}
+Restart=on-failure
+RestartSec=5
+WatchdogSec=90
+StateDirectory=docker-hostmanager
+
+# Only the hosts file (and backups) may be written
+ProtectSystem=strict
+ReadWritePaths={read_write}
+ProtectHome=yes
+PrivateTmp=yes
+PrivateDevices=yes
+NoNewPrivileges=yes
+CapabilityBoundingSet=
+ProtectKernelTunables=yes
+ProtectKernelModules=yes
+ProtectKernelLogs=yes
+ProtectControlGroups=yes
+ProtectClock=yes
+ProtectHostname=yes
+RestrictAddressFamilies=AF_UNIX AF_INET AF_INET6
+RestrictNamespaces=yes
+RestrictRealtime=yes
+RestrictSUIDSGID=yes
+LockPersonality=yes
+MemoryDenyWriteExecute=yes
+SystemCallArchitectures=native
+
+[Install]
+WantedBy=multi-user.target
+",
+        hosts_file = hosts_file.display(),
+        exec_start = command.join(" "),
+        read_write = read_write
+            .iter()
+            .map(|p| quote(p))
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// Quotes a word for a unit file: specifiers (`%`) and variables (`$`) are
+/// escaped, and words with spaces or quotes are put in double quotes.
+fn quote(word: &str) -> String {
+    let escaped = word.replace('%', "%%").replace('$', "$$");
+    if !escaped.is_empty()
+        && escaped
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,@+%$*?[]{}".contains(c))
+    {
+        return escaped;
+    }
+    format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::indexing_slicing
+)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_file() {
+        let unit = unit_file(
+            &[
+                "/usr/local/bin/docker-hostmanager".to_string(),
+                "--tld=.local".to_string(),
+                "--filter=label=team=web shop".to_string(),
+                "sync".to_string(),
+                "/etc/hosts".to_string(),
+            ],
+            Path::new("/etc/hosts"),
+            &[PathBuf::from(STATE_DIRECTORY)],
+        );
+        assert!(
+            unit.contains("\nExecStart=/usr/local/bin/docker-hostmanager --tld=.local \"--filter=label=team=web shop\" sync /etc/hosts\n"),
+            "{unit}"
+        );
+        assert!(
+            unit.contains("\nReadWritePaths=/etc/hosts /var/lib/docker-hostmanager\n"),
+            "{unit}"
+        );
+        assert!(unit.contains("\nType=notify\n"), "{unit}");
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("/etc/hosts"), "/etc/hosts");
+        assert_eq!(quote("100%"), "100%%");
+        assert_eq!(quote("$HOME"), "$$HOME");
+        assert_eq!(quote("a \"b\""), "\"a \\\"b\\\"\"");
+        assert_eq!(quote(""), "\"\"");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_notify() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let receiver = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+
+        let notifier = Notifier::connect(&path).unwrap();
+        notifier.notify("READY=1\nSTATUS=1 containers, 2 hostnames");
+
+        let mut buffer = [0; 64];
+        let length = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(
+            &buffer[..length],
+            b"READY=1\nSTATUS=1 containers, 2 hostnames"
+        );
+    }
+}
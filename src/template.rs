@@ -0,0 +1,318 @@
+//! Template engine for `DOMAIN_NAME` and domain labels.
+//!
+//! A template is plain text with `{...}` expressions:
+//!
+//! - `{VAR}` — a container environment variable
+//! - `{VAR:-default}` — falls back to `default` when `VAR` is unset or empty
+//! - `{label:com.example.x}` — a container label
+//! - `{network}` / `{service}` — the network being published on and the
+//!   compose service (or container name)
+//! - `{VAR|lower}`, `{VAR|upper}`, `{VAR|slug}` — filters, which can be chained
+//!
+//! Unknown variables without a default are left in place, so the writer can
+//! skip the resulting hostname and report it. Malformed expressions are errors.
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// Values available to a template.
+pub struct Context<'a> {
+    pub env: &'a HashMap<String, String>,
+    pub labels: &'a HashMap<String, String>,
+    pub service: &'a str,
+    /// The network the result will be published on; `None` leaves `{network}` unresolved.
+    pub network: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    /// A `{` without a matching `}`.
+    Unclosed {
+        position: usize,
+    },
+    /// A `{` inside an expression.
+    Nested {
+        position: usize,
+    },
+    /// `{}` or an expression with only a default or filters.
+    EmptyExpression {
+        position: usize,
+    },
+    UnknownFilter(String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unclosed { position } => write!(f, "unclosed '{{' at position {position}"),
+            Self::Nested { position } => write!(f, "unexpected '{{' at position {position}"),
+            Self::EmptyExpression { position } => {
+                write!(f, "empty expression at position {position}")
+            }
+            Self::UnknownFilter(name) => write!(f, "unknown filter '{name}'"),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// Renders `input` against `ctx`.
+pub fn render(input: &str, ctx: &Context<'_>) -> Result<String, TemplateError> {
+    let mut result = String::with_capacity(input.len());
+    let mut chars = input.char_indices();
+    while let Some((position, c)) = chars.next() {
+        if c != '{' {
+            result.push(c);
+            continue;
+        }
+
+        let mut expression = String::new();
+        let mut closed = false;
+        for (inner_position, next_c) in chars.by_ref() {
+            match next_c {
+                '}' => {
+                    closed = true;
+                    break;
+                }
+                '{' => {
+                    return Err(TemplateError::Nested {
+                        position: inner_position,
+                    })
+                }
+                _ => expression.push(next_c),
+            }
+        }
+        if !closed {
+            return Err(TemplateError::Unclosed { position });
+        }
+
+        if let Some(value) = evaluate(&expression, ctx, position)? {
+            result.push_str(&value);
+        } else {
+            // Leave unresolved variables in place for the writer to skip
+            result.push('{');
+            result.push_str(&expression);
+            result.push('}');
+        }
+    }
+    Ok(result)
+}
+
+/// Returns `true` if `input` references the `{network}` built-in, i.e. it
+/// renders differently for every network the container is attached to.
+pub fn uses_network(input: &str) -> bool {
+    let mut rest = input;
+    while let Some(start) = rest.find('{') {
+        let Some(after) = rest.get(start + 1..) else {
+            break;
+        };
+        let Some(end) = after.find('}') else { break };
+        let expression = after.get(..end).unwrap_or_default();
+        if parse(expression).0 == "network" {
+            return true;
+        }
+        rest = after.get(end + 1..).unwrap_or_default();
+    }
+    false
+}
+
+/// Splits an expression into its variable name, optional default and filters.
+fn parse(expression: &str) -> (&str, Option<&str>, Vec<&str>) {
+    let mut parts = expression.split('|');
+    let head = parts.next().unwrap_or_default();
+    let filters = parts.map(str::trim).collect();
+    match head.split_once(":-") {
+        Some((name, default)) => (name.trim(), Some(default), filters),
+        None => (head.trim(), None, filters),
+    }
+}
+
+fn evaluate(
+    expression: &str,
+    ctx: &Context<'_>,
+    position: usize,
+) -> Result<Option<String>, TemplateError> {
+    let (name, default, filters) = parse(expression);
+    if name.is_empty() {
+        return Err(TemplateError::EmptyExpression { position });
+    }
+    // Validate filters up front so a typo is reported even when the variable is unset
+    for filter in &filters {
+        if !matches!(*filter, "lower" | "upper" | "slug") {
+            return Err(TemplateError::UnknownFilter((*filter).to_string()));
+        }
+    }
+
+    let found = match name {
+        "network" => ctx.network.map(str::to_string),
+        "service" => Some(ctx.service.to_string()),
+        _ => name.strip_prefix("label:").map_or_else(
+            || ctx.env.get(name).cloned(),
+            |label| ctx.labels.get(label.trim()).cloned(),
+        ),
+    };
+    let value = match (found, default) {
+        (Some(v), Some(d)) if v.is_empty() => Some(d.to_string()),
+        (None, Some(d)) => Some(d.to_string()),
+        (v, _) => v,
+    };
+
+    Ok(value.map(|v| {
+        filters.iter().fold(v, |acc, filter| match *filter {
+            "lower" => acc.to_lowercase(),
+            "upper" => acc.to_uppercase(),
+            _ => slugify(&acc),
+        })
+    }))
+}
+
+/// Lowercases `value` and collapses every run of characters that aren't valid
+/// in a DNS label into a single `-`.
+fn slugify(value: &str) -> String {
+    let mut slug = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_matches('-').to_string()
+}
+
+#[cfg(test)]
+#[allow(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::indexing_slicing
+)]
+mod tests {
+    use super::*;
+
+    fn render_with(input: &str, network: Option<&str>) -> Result<String, TemplateError> {
+        let env = HashMap::from([
+            ("APP_ENV".to_string(), "Staging".to_string()),
+            ("EMPTY".to_string(), String::new()),
+            ("BRANCH".to_string(), "feature/New_Login".to_string()),
+        ]);
+        let labels = HashMap::from([("com.example.tenant".to_string(), "acme".to_string())]);
+        render(
+            input,
+            &Context {
+                env: &env,
+                labels: &labels,
+                service: "web",
+                network,
+            },
+        )
+    }
+
+    #[test]
+    fn test_render_variables() {
+        assert_eq!(
+            render_with("{APP_ENV}.local", None).unwrap(),
+            "Staging.local"
+        );
+        assert_eq!(
+            render_with("plain.local", None).unwrap(),
+            "plain.local",
+            "text without expressions is returned unchanged"
+        );
+    }
+
+    #[test]
+    fn test_render_unknown_variable_left_in_place() {
+        assert_eq!(
+            render_with("{MISSING}.local", None).unwrap(),
+            "{MISSING}.local"
+        );
+        assert_eq!(
+            render_with("{network}.local", None).unwrap(),
+            "{network}.local",
+            "{{network}} is unresolved outside of a network context"
+        );
+    }
+
+    #[test]
+    fn test_render_defaults() {
+        assert_eq!(
+            render_with("{MISSING:-dev}.local", None).unwrap(),
+            "dev.local"
+        );
+        assert_eq!(
+            render_with("{EMPTY:-dev}.local", None).unwrap(),
+            "dev.local",
+            "an empty value falls back to the default"
+        );
+        assert_eq!(
+            render_with("{APP_ENV:-dev}.local", None).unwrap(),
+            "Staging.local"
+        );
+    }
+
+    #[test]
+    fn test_render_filters() {
+        assert_eq!(
+            render_with("{APP_ENV|lower}.local", None).unwrap(),
+            "staging.local"
+        );
+        assert_eq!(
+            render_with("{APP_ENV|upper}.local", None).unwrap(),
+            "STAGING.local"
+        );
+        assert_eq!(
+            render_with("{BRANCH|slug}.app.local", None).unwrap(),
+            "feature-new-login.app.local"
+        );
+        assert_eq!(
+            render_with("{MISSING:-My App|slug|upper}.local", None).unwrap(),
+            "MY-APP.local",
+            "filters apply to the default too and chain left to right"
+        );
+    }
+
+    #[test]
+    fn test_render_labels_and_builtins() {
+        assert_eq!(
+            render_with("{label:com.example.tenant}.app.local", None).unwrap(),
+            "acme.app.local"
+        );
+        assert_eq!(
+            render_with("{label:com.example.missing:-shared}.app.local", None).unwrap(),
+            "shared.app.local"
+        );
+        assert_eq!(
+            render_with("{service}.{network}.local", Some("backend")).unwrap(),
+            "web.backend.local"
+        );
+    }
+
+    #[test]
+    fn test_render_malformed() {
+        assert_eq!(
+            render_with("{APP_ENV.local", None),
+            Err(TemplateError::Unclosed { position: 0 })
+        );
+        assert_eq!(
+            render_with("a{APP{ENV}}", None),
+            Err(TemplateError::Nested { position: 5 })
+        );
+        assert_eq!(
+            render_with("{}.local", None),
+            Err(TemplateError::EmptyExpression { position: 0 })
+        );
+        assert_eq!(
+            render_with("{MISSING|camel}.local", None),
+            Err(TemplateError::UnknownFilter("camel".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_uses_network() {
+        assert!(uses_network("{service}.{network}.local"));
+        assert!(uses_network("{ network |slug}.local"));
+        assert!(!uses_network("{APP_ENV}.local"));
+        assert!(!uses_network("{label:network}.local"));
+    }
+}
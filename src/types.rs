@@ -1,20 +1,31 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ContainerInfo {
     pub id: String,
     pub name: String,
     pub ip_address: Option<String>,
     pub networks: HashMap<String, NetworkInfo>,
     pub domain_names: Vec<String>,
+    /// Subdomains that wildcard domain names (`*.app.test`) expand to.
+    pub subdomains: Vec<String>,
+    /// Generated hostnames that must not be published.
+    pub exclude_names: Vec<String>,
+    /// Overrides the global TLD for this container, and publishes
+    /// `<name><tld>` on each of its networks.
+    pub tld: Option<String>,
+    /// Compose project the container belongs to, if any.
+    pub project: Option<String>,
     pub running: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct NetworkInfo {
     pub ip_address: String,
     pub aliases: Vec<String>,
+    /// Gateway address of the network, i.e. the host side of the bridge.
+    pub gateway: Option<String>,
 }
 
 impl ContainerInfo {
@@ -26,10 +37,15 @@ impl ContainerInfo {
     pub fn get_hostnames(&self, tld: &str) -> Vec<(String, Vec<String>)> {
         let mut result = Vec::new();
 
+        let effective_tld = self.tld.as_deref().unwrap_or(tld);
+
         // Global IP address with simple hostname
         if let Some(ip) = &self.ip_address {
-            let mut hosts = vec![format!("{}{}", self.name, tld)];
-            hosts.extend(self.domain_names.clone());
+            let mut hosts = vec![format!("{}{}", self.name, effective_tld)];
+            for domain in &self.domain_names {
+                hosts.extend(self.expand_wildcard(domain));
+            }
+            hosts.retain(|h| !self.exclude_names.contains(h));
             result.push((ip.clone(), hosts));
         }
 
@@ -40,6 +56,11 @@ impl ContainerInfo {
             // Add container name with network suffix
             hosts.push(format!("{}.{}", self.name, network_name));
 
+            // A per-container TLD publishes the bare name on every network too
+            if let (None, Some(own_tld)) = (&self.ip_address, &self.tld) {
+                hosts.push(format!("{}{own_tld}", self.name));
+            }
+
             // Add all aliases with network suffix
             for alias in &network_info.aliases {
                 hosts.push(format!("{alias}.{network_name}"));
@@ -48,31 +69,73 @@ impl ContainerInfo {
             // Also support DOMAIN_NAME env var with network prefix (network:hostname format)
             for domain in &self.domain_names {
                 if let Some((net, hostname)) = domain.split_once(':') {
-                    // Match if network name is exactly the same, or ends with _<net>
-                    // This allows "default:hostname" to match "project_default" network
-                    let matches = network_name == net
-                        || network_name.ends_with(&format!("_{net}"))
-                        || network_name.ends_with(&format!("-{net}"));
-
-                    if matches {
-                        hosts.push(hostname.to_string());
+                    if network_matches(network_name, net) {
+                        hosts.extend(self.expand_wildcard(hostname));
                     }
                 } else if self.ip_address.is_none() {
                     // If no global IP, add plain domain names to all networks
-                    hosts.push(domain.clone());
+                    hosts.extend(self.expand_wildcard(domain));
                 }
             }
 
             if !hosts.is_empty() {
                 // Deduplicate hostnames while preserving order
                 let mut seen = std::collections::HashSet::new();
-                hosts.retain(|h| seen.insert(h.clone()));
+                hosts.retain(|h| !self.exclude_names.contains(h) && seen.insert(h.clone()));
                 result.push((network_info.ip_address.clone(), hosts));
             }
         }
 
         result
     }
+
+    /// Expands a wildcard domain (`*.app.test`) into one hostname per entry in
+    /// `subdomains`, since hosts files can't express wildcards. Without any
+    /// subdomains the wildcard is returned as-is and later skipped by the writer.
+    /// Non-wildcard domains are returned unchanged.
+    pub fn expand_wildcard(&self, domain: &str) -> Vec<String> {
+        match domain.strip_prefix("*.") {
+            Some(suffix) if !self.subdomains.is_empty() => self
+                .subdomains
+                .iter()
+                .map(|sub| format!("{sub}.{suffix}"))
+                .collect(),
+            _ => vec![domain.to_string()],
+        }
+    }
+
+    /// The hostnames the domain names expand to, without their network
+    /// prefixes, whichever networks the container is on.
+    pub fn domain_hostnames(&self) -> Vec<String> {
+        self.domain_names
+            .iter()
+            .flat_map(|d| self.expand_wildcard(bare_domain(d)))
+            .collect()
+    }
+
+    /// Returns `true` if `hostname` is only generated through a wildcard domain
+    /// expansion. Exact names take precedence over these when claiming.
+    pub fn is_wildcard_hostname(&self, hostname: &str) -> bool {
+        let expanded =
+            self.domain_names.iter().map(|d| bare_domain(d)).any(|d| {
+                d.starts_with("*.") && self.expand_wildcard(d).iter().any(|h| h == hostname)
+            });
+        expanded && !self.domain_names.iter().any(|d| bare_domain(d) == hostname)
+    }
+}
+
+/// Returns `true` if `wanted`, as written in a `network:hostname` domain or a
+/// label, refers to `network_name`: either exactly, or as the `_<net>`/`-<net>`
+/// suffix compose adds, so `default` matches the `project_default` network.
+pub fn network_matches(network_name: &str, wanted: &str) -> bool {
+    network_name == wanted
+        || network_name.ends_with(&format!("_{wanted}"))
+        || network_name.ends_with(&format!("-{wanted}"))
+}
+
+/// Strips the network prefix from a `network:hostname` domain entry.
+fn bare_domain(domain: &str) -> &str {
+    domain.split_once(':').map_or(domain, |(_, h)| h)
 }
 
 #[cfg(test)]
@@ -91,9 +154,8 @@ mod tests {
             id: "abc123".to_string(),
             name: "test".to_string(),
             ip_address: Some("172.17.0.2".to_string()),
-            networks: HashMap::new(),
-            domain_names: vec![],
             running: true,
+            ..Default::default()
         };
         assert!(container.has_exposed_ports());
 
@@ -101,19 +163,16 @@ mod tests {
             id: "abc123".to_string(),
             name: "test".to_string(),
             ip_address: Some("172.17.0.2".to_string()),
-            networks: HashMap::new(),
-            domain_names: vec![],
             running: false,
+            ..Default::default()
         };
         assert!(!container_not_running.has_exposed_ports());
 
         let container_no_ip = ContainerInfo {
             id: "abc123".to_string(),
             name: "test".to_string(),
-            ip_address: None,
-            networks: HashMap::new(),
-            domain_names: vec![],
             running: true,
+            ..Default::default()
         };
         assert!(!container_no_ip.has_exposed_ports());
     }
@@ -124,9 +183,8 @@ mod tests {
             id: "abc123".to_string(),
             name: "nginx".to_string(),
             ip_address: Some("172.17.0.2".to_string()),
-            networks: HashMap::new(),
-            domain_names: vec![],
             running: true,
+            ..Default::default()
         };
 
         let hostnames = container.get_hostnames(".docker");
@@ -141,9 +199,9 @@ mod tests {
             id: "abc123".to_string(),
             name: "web".to_string(),
             ip_address: Some("172.17.0.2".to_string()),
-            networks: HashMap::new(),
             domain_names: vec!["example.com".to_string(), "www.example.com".to_string()],
             running: true,
+            ..Default::default()
         };
 
         let hostnames = container.get_hostnames(".docker");
@@ -162,16 +220,16 @@ mod tests {
             NetworkInfo {
                 ip_address: "172.18.0.2".to_string(),
                 aliases: vec!["web".to_string(), "www".to_string()],
+                ..Default::default()
             },
         );
 
         let container = ContainerInfo {
             id: "abc123".to_string(),
             name: "web".to_string(),
-            ip_address: None,
             networks,
-            domain_names: vec![],
             running: true,
+            ..Default::default()
         };
 
         let hostnames = container.get_hostnames(".docker");
@@ -189,19 +247,20 @@ mod tests {
             NetworkInfo {
                 ip_address: "172.18.0.2".to_string(),
                 aliases: vec!["web".to_string()],
+                ..Default::default()
             },
         );
 
         let container = ContainerInfo {
             id: "abc123".to_string(),
             name: "web".to_string(),
-            ip_address: None,
             networks,
             domain_names: vec![
                 "myapp:api.local".to_string(),
                 "myapp:admin.local".to_string(),
             ],
             running: true,
+            ..Default::default()
         };
 
         let hostnames = container.get_hostnames(".docker");
@@ -220,19 +279,20 @@ mod tests {
             NetworkInfo {
                 ip_address: "172.18.0.2".to_string(),
                 aliases: vec!["urq-app".to_string()],
+                ..Default::default()
             },
         );
 
         let container = ContainerInfo {
             id: "abc123".to_string(),
             name: "urq-app".to_string(),
-            ip_address: None,
             networks,
             domain_names: vec![
                 "default:urq.app.local".to_string(), // Simple network name in env var
                 "default:urq.example.com".to_string(),
             ],
             running: true,
+            ..Default::default()
         };
 
         let hostnames = container.get_hostnames(".docker");
@@ -254,16 +314,17 @@ mod tests {
             NetworkInfo {
                 ip_address: "172.20.0.5".to_string(),
                 aliases: vec!["web".to_string()],
+                ..Default::default()
             },
         );
 
         let container = ContainerInfo {
             id: "xyz789".to_string(),
             name: "web".to_string(),
-            ip_address: None,
             networks,
             domain_names: vec!["default:api.example.com".to_string()],
             running: true,
+            ..Default::default()
         };
 
         let hostnames = container.get_hostnames(".docker");
@@ -283,16 +344,17 @@ mod tests {
             NetworkInfo {
                 ip_address: "172.21.0.3".to_string(),
                 aliases: vec!["db".to_string()],
+                ..Default::default()
             },
         );
 
         let container = ContainerInfo {
             id: "def456".to_string(),
             name: "db".to_string(),
-            ip_address: None,
             networks,
             domain_names: vec!["default:postgres.local".to_string()],
             running: true,
+            ..Default::default()
         };
 
         let hostnames = container.get_hostnames(".docker");
@@ -312,16 +374,17 @@ mod tests {
             NetworkInfo {
                 ip_address: "172.22.0.2".to_string(),
                 aliases: vec!["app".to_string()],
+                ..Default::default()
             },
         );
 
         let container = ContainerInfo {
             id: "exact123".to_string(),
             name: "app".to_string(),
-            ip_address: None,
             networks,
             domain_names: vec!["default:exact-match.test".to_string()],
             running: true,
+            ..Default::default()
         };
 
         let hostnames = container.get_hostnames(".docker");
@@ -341,16 +404,17 @@ mod tests {
             NetworkInfo {
                 ip_address: "172.23.0.2".to_string(),
                 aliases: vec!["app".to_string()],
+                ..Default::default()
             },
         );
 
         let container = ContainerInfo {
             id: "false123".to_string(),
             name: "app".to_string(),
-            ip_address: None,
             networks,
             domain_names: vec!["default:shouldnot.match".to_string()],
             running: true,
+            ..Default::default()
         };
 
         let hostnames = container.get_hostnames(".docker");
@@ -370,6 +434,7 @@ mod tests {
             NetworkInfo {
                 ip_address: "172.24.0.2".to_string(),
                 aliases: vec!["web".to_string()],
+                ..Default::default()
             },
         );
         networks.insert(
@@ -377,19 +442,20 @@ mod tests {
             NetworkInfo {
                 ip_address: "172.25.0.2".to_string(),
                 aliases: vec!["web".to_string()],
+                ..Default::default()
             },
         );
 
         let container = ContainerInfo {
             id: "multi123".to_string(),
             name: "web".to_string(),
-            ip_address: None,
             networks,
             domain_names: vec![
                 "default:public.example.com".to_string(),
                 "internal:private.local".to_string(),
             ],
             running: true,
+            ..Default::default()
         };
 
         let hostnames = container.get_hostnames(".docker");
@@ -424,6 +490,7 @@ mod tests {
             NetworkInfo {
                 ip_address: "172.18.0.2".to_string(),
                 aliases: vec!["web".to_string()],
+                ..Default::default()
             },
         );
         networks.insert(
@@ -431,16 +498,16 @@ mod tests {
             NetworkInfo {
                 ip_address: "172.19.0.2".to_string(),
                 aliases: vec!["web".to_string(), "api".to_string()],
+                ..Default::default()
             },
         );
 
         let container = ContainerInfo {
             id: "abc123".to_string(),
             name: "web".to_string(),
-            ip_address: None,
             networks,
-            domain_names: vec![],
             running: true,
+            ..Default::default()
         };
 
         let hostnames = container.get_hostnames(".docker");
@@ -460,19 +527,20 @@ mod tests {
             NetworkInfo {
                 ip_address: "172.19.0.3".to_string(),
                 aliases: vec!["urq-web-1".to_string(), "web".to_string()],
+                ..Default::default()
             },
         );
 
         let container = ContainerInfo {
             id: "web123".to_string(),
             name: "urq-web-1".to_string(),
-            ip_address: None,
             networks,
             domain_names: vec![
                 "default:urq.app.local".to_string(),
                 "default:urq.example.com".to_string(),
             ],
             running: true,
+            ..Default::default()
         };
 
         let hostnames = container.get_hostnames(".docker");
@@ -493,4 +561,76 @@ mod tests {
         assert!(hostname_list.contains(&"urq.app.local".to_string()));
         assert!(hostname_list.contains(&"urq.example.com".to_string()));
     }
+
+    #[test]
+    fn test_get_hostnames_expands_wildcard_subdomains() {
+        let mut networks = HashMap::new();
+        networks.insert(
+            "myapp".to_string(),
+            NetworkInfo {
+                ip_address: "172.18.0.2".to_string(),
+                ..Default::default()
+            },
+        );
+
+        let container = ContainerInfo {
+            id: "tenant123".to_string(),
+            name: "app".to_string(),
+            networks,
+            domain_names: vec!["*.app.test".to_string()],
+            subdomains: vec!["acme".to_string(), "globex".to_string()],
+            running: true,
+            ..Default::default()
+        };
+
+        let hostnames = container.get_hostnames(".docker");
+        assert_eq!(hostnames.len(), 1);
+        assert_eq!(
+            hostnames[0].1,
+            vec!["app.myapp", "acme.app.test", "globex.app.test"]
+        );
+        assert!(container.is_wildcard_hostname("acme.app.test"));
+        assert!(!container.is_wildcard_hostname("app.myapp"));
+    }
+
+    #[test]
+    fn test_get_hostnames_keeps_wildcard_without_subdomains() {
+        let container = ContainerInfo {
+            id: "tenant456".to_string(),
+            name: "app".to_string(),
+            ip_address: Some("172.17.0.2".to_string()),
+            domain_names: vec!["*.app.test".to_string()],
+            running: true,
+            ..Default::default()
+        };
+
+        let hostnames = container.get_hostnames(".docker");
+        assert_eq!(hostnames[0].1, vec!["app.docker", "*.app.test"]);
+    }
+
+    #[test]
+    fn test_get_hostnames_with_tld_override_and_exclusions() {
+        let container = ContainerInfo {
+            id: "abc123".to_string(),
+            name: "web".to_string(),
+            networks: HashMap::from([(
+                "bridge".to_string(),
+                NetworkInfo {
+                    ip_address: "172.17.0.2".to_string(),
+                    ..Default::default()
+                },
+            )]),
+            domain_names: vec!["a.example.com".to_string(), "b.example.com".to_string()],
+            exclude_names: vec!["a.example.com".to_string()],
+            tld: Some(".test".to_string()),
+            running: true,
+            ..Default::default()
+        };
+
+        let hostnames = container.get_hostnames(".docker");
+        assert_eq!(
+            hostnames[0].1,
+            vec!["web.bridge", "web.test", "b.example.com"]
+        );
+    }
 }